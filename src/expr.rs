@@ -0,0 +1,414 @@
+use std::{collections::HashMap, fmt};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// An integer expression from a `let` line or a `${...}`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    /// A `let` from before the first hotkey, its value is known when parsing.
+    Constant(String, i64),
+    /// A variable set by a `let` action while the script runs.
+    Variable(String),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Op {
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div | Op::Rem => 2,
+        }
+    }
+
+    fn apply(self, left: i64, right: i64) -> Option<i64> {
+        match self {
+            Op::Add => left.checked_add(right),
+            Op::Sub => left.checked_sub(right),
+            Op::Mul => left.checked_mul(right),
+            Op::Div => left.checked_div(right),
+            Op::Rem => left.checked_rem(right),
+        }
+    }
+
+    fn symbol(self) -> char {
+        match self {
+            Op::Add => '+',
+            Op::Sub => '-',
+            Op::Mul => '*',
+            Op::Div => '/',
+            Op::Rem => '%',
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Option<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                number.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Number(number.parse().ok()?));
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Name(name));
+            continue;
+        }
+
+        tokens.push(match c {
+            '+' => Token::Op(Op::Add),
+            '-' => Token::Op(Op::Sub),
+            '*' => Token::Op(Op::Mul),
+            '/' => Token::Op(Op::Div),
+            '%' => Token::Op(Op::Rem),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            _ => return None,
+        });
+        chars.next();
+    }
+
+    Some(tokens)
+}
+
+/// Recursive descent over the tokens of one expression.
+struct ExprParser<'a> {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+    constants: &'a HashMap<String, i64>,
+}
+
+impl ExprParser<'_> {
+    fn binary(&mut self, precedence: u8) -> Option<Expr> {
+        let mut left = self.unary()?;
+
+        while let Some(&Token::Op(op)) = self.tokens.peek() {
+            if op.precedence() < precedence {
+                break;
+            }
+            self.tokens.next();
+
+            let right = self.binary(op.precedence() + 1)?;
+            left = Expr::Binary(Box::new(left), op, Box::new(right));
+        }
+
+        Some(left)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        match self.tokens.next()? {
+            Token::Number(number) => Some(Expr::Number(number)),
+            Token::Name(name) => Some(match self.constants.get(&name) {
+                Some(value) => Expr::Constant(name, *value),
+                None => Expr::Variable(name),
+            }),
+            Token::Op(Op::Sub) => match self.unary()? {
+                Expr::Number(number) => Some(Expr::Number(-number)),
+                expr => Some(Expr::Binary(
+                    Box::new(Expr::Number(0)),
+                    Op::Sub,
+                    Box::new(expr),
+                )),
+            },
+            Token::Open => {
+                let expr = self.binary(0)?;
+                (self.tokens.next()? == Token::Close).then_some(expr)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Expr {
+    /// Parses an expression, names found in `constants` are replaced by their
+    /// values and any others are left as variables.
+    pub fn parse(source: &str, constants: &HashMap<String, i64>) -> Option<Self> {
+        let mut parser = ExprParser {
+            tokens: tokenize(source)?.into_iter().peekable(),
+            constants,
+        };
+
+        let expr = parser.binary(0)?;
+        parser.tokens.next().is_none().then_some(expr)
+    }
+
+    /// Evaluates the expression, `None` on overflow or division by zero.
+    /// Variables that haven't been set yet are 0.
+    pub fn eval(&self, variables: &HashMap<String, i64>) -> Option<i64> {
+        match self {
+            Expr::Number(number) | Expr::Constant(_, number) => Some(*number),
+            Expr::Variable(name) => Some(variables.get(name).copied().unwrap_or(0)),
+            Expr::Binary(left, op, right) => {
+                op.apply(left.eval(variables)?, right.eval(variables)?)
+            }
+        }
+    }
+
+    /// The value of the expression if it doesn't use any variables.
+    pub fn constant(&self) -> Option<i64> {
+        self.variables()
+            .is_empty()
+            .then(|| self.eval(&HashMap::new()))
+            .flatten()
+    }
+
+    /// The names of the variables the expression reads.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) | Expr::Constant(..) => Vec::new(),
+            Expr::Variable(name) => vec![name.as_str()],
+            Expr::Binary(left, _, right) => {
+                let mut variables = left.variables();
+                variables.extend(right.variables());
+                variables
+            }
+        }
+    }
+
+    /// Writes a value that can appear anywhere a number can, wrapping
+    /// anything but a plain number in `${...}`.
+    pub fn write_interpolated(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(number) => write!(f, "{number}"),
+            expr => write!(f, "${{{expr}}}"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(number) => write!(f, "{number}"),
+            Expr::Constant(name, _) | Expr::Variable(name) => write!(f, "{name}"),
+            Expr::Binary(left, op, right) => {
+                // Operators are left associative, so only a right hand side of
+                // the same precedence needs parentheses
+                let needs_parens = |expr: &Expr, is_right: bool| match expr {
+                    Expr::Binary(_, inner, _) => {
+                        inner.precedence() < op.precedence()
+                            || (is_right && inner.precedence() == op.precedence())
+                    }
+                    _ => false,
+                };
+
+                for (expr, is_right) in [(left, false), (right, true)] {
+                    if is_right {
+                        write!(f, " {} ", op.symbol())?;
+                    }
+
+                    if needs_parens(expr, is_right) {
+                        write!(f, "({expr})")?;
+                    } else {
+                        write!(f, "{expr}")?;
+                    }
+                }
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextPart {
+    Literal(String),
+    Expr(Expr),
+}
+
+/// Text with `${...}` expressions in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Text(pub Vec<TextPart>);
+
+impl Text {
+    pub fn parse(source: &str, constants: &HashMap<String, i64>) -> Option<Self> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+
+        while let Some((literal, after)) = rest.split_once("${") {
+            let (expr, after) = after.split_once('}')?;
+
+            if !literal.is_empty() {
+                parts.push(TextPart::Literal(literal.to_string()));
+            }
+            // Names are case insensitive like the rest of a script
+            parts.push(TextPart::Expr(Expr::parse(
+                &expr.to_lowercase(),
+                constants,
+            )?));
+            rest = after;
+        }
+
+        if !rest.is_empty() {
+            parts.push(TextPart::Literal(rest.to_string()));
+        }
+
+        Some(Self(parts))
+    }
+
+    /// The names of the variables the text reads.
+    pub fn variables(&self) -> Vec<&str> {
+        self.0
+            .iter()
+            .flat_map(|part| match part {
+                TextPart::Literal(_) => Vec::new(),
+                TextPart::Expr(expr) => expr.variables(),
+            })
+            .collect()
+    }
+
+    /// Builds the text, using `eval` to get the value of each expression.
+    pub fn render(&self, eval: impl Fn(&Expr) -> i64) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                TextPart::Literal(literal) => literal.clone(),
+                TextPart::Expr(expr) => eval(expr).to_string(),
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Text {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for part in &self.0 {
+            match part {
+                TextPart::Literal(literal) => write!(f, "{literal}")?,
+                TextPart::Expr(expr) => write!(f, "${{{expr}}}")?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(source: &str) -> Option<i64> {
+        Expr::parse(source, &HashMap::new())?.eval(&HashMap::new())
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9));
+        assert_eq!(eval("10 - 4 - 3"), Some(3));
+        assert_eq!(eval("100 / 10 / 5"), Some(2));
+        assert_eq!(eval("7 % 4 * 2"), Some(6));
+        assert_eq!(eval("-3 + 5"), Some(2));
+        assert_eq!(eval("-(2 + 3) * 2"), Some(-10));
+        assert_eq!(eval("2 * -3"), Some(-6));
+    }
+
+    #[test]
+    fn invalid() {
+        for source in [
+            "",
+            "1 +",
+            "(1",
+            "1)",
+            "1 2",
+            "2 ^ 3",
+            "99999999999999999999",
+        ] {
+            assert_eq!(Expr::parse(source, &HashMap::new()), None, "{source}");
+        }
+    }
+
+    #[test]
+    fn failed_evaluation() {
+        assert_eq!(eval("1 / 0"), None);
+        assert_eq!(eval("1 % 0"), None);
+        assert_eq!(eval("9223372036854775807 + 1"), None);
+        assert_eq!(eval("-9223372036854775807 - 2"), None);
+        assert_eq!(eval("4611686018427387904 * 2"), None);
+    }
+
+    #[test]
+    fn variables_and_constants() {
+        let constants = HashMap::from([("delay".to_string(), 10)]);
+        let expr = Expr::parse("delay * presses + 1", &constants).unwrap();
+
+        assert_eq!(expr.variables(), vec!["presses"]);
+        assert_eq!(expr.constant(), None);
+        // Variables that were never set are 0
+        assert_eq!(expr.eval(&HashMap::new()), Some(1));
+        assert_eq!(
+            expr.eval(&HashMap::from([("presses".to_string(), 3)])),
+            Some(31)
+        );
+        assert_eq!(
+            Expr::parse("delay * 2", &constants).unwrap().constant(),
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn display() {
+        for (source, shown) in [
+            ("1 + 2 * 3", "1 + 2 * 3"),
+            ("(1 + 2) * 3", "(1 + 2) * 3"),
+            ("1 - (2 - 3)", "1 - (2 - 3)"),
+            ("(1 - 2) - 3", "1 - 2 - 3"),
+            ("((a))", "a"),
+        ] {
+            let expr = Expr::parse(source, &HashMap::new()).unwrap();
+            assert_eq!(expr.to_string(), shown);
+        }
+    }
+
+    #[test]
+    fn text() {
+        let constants = HashMap::from([("delay".to_string(), 10)]);
+        let text =
+            Text::parse("waited ${delay * 2}ms after ${Presses} presses", &constants).unwrap();
+
+        assert_eq!(text.variables(), vec!["presses"]);
+        assert_eq!(
+            text.render(|expr| expr.eval(&HashMap::new()).unwrap()),
+            "waited 20ms after 0 presses"
+        );
+        assert_eq!(
+            text.to_string(),
+            "waited ${delay * 2}ms after ${presses} presses"
+        );
+        assert_eq!(Text::parse("no end ${1 + 1", &constants), None);
+        assert_eq!(
+            Text::parse("plain", &constants).unwrap().to_string(),
+            "plain"
+        );
+    }
+}
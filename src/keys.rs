@@ -0,0 +1,353 @@
+use std::fmt;
+
+use enigo::Key;
+use xkeysym::Keysym;
+
+/// A named key. `code` is the evdev code used to match triggers and `key` is
+/// what Enigo sends for events. Keys without an Enigo equivalent are sent as
+/// raw keycodes instead, so every key with a code can be used for both.
+pub struct KeyInfo {
+    pub names: &'static [&'static str],
+    pub code: Option<u16>,
+    pub key: Option<Key>,
+}
+
+/// The highest evdev key code, `KEY_MAX` in the kernel.
+pub const KEY_MAX: u16 = 0x2ff;
+
+/// A key as it is sent by an `event` action.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKey {
+    Key(Key),
+    Raw(u16),
+}
+
+const fn entry(code: u16, key: Key, names: &'static [&'static str]) -> KeyInfo {
+    KeyInfo {
+        names,
+        code: Some(code),
+        key: Some(key),
+    }
+}
+
+const fn code_only(code: u16, names: &'static [&'static str]) -> KeyInfo {
+    KeyInfo {
+        names,
+        code: Some(code),
+        key: None,
+    }
+}
+
+const fn key_only(key: Key, names: &'static [&'static str]) -> KeyInfo {
+    KeyInfo {
+        names,
+        code: None,
+        key: Some(key),
+    }
+}
+
+/// Every known key. The first name of each entry is the one used when writing
+/// scripts back out, and the first entry for a code wins when looking it up.
+#[rustfmt::skip]
+pub static KEYS: &[KeyInfo] = &[
+    entry(1, Key::Escape, &["escape", "esc"]),
+    entry(2, Key::Unicode('1'), &["1"]),
+    entry(3, Key::Unicode('2'), &["2"]),
+    entry(4, Key::Unicode('3'), &["3"]),
+    entry(5, Key::Unicode('4'), &["4"]),
+    entry(6, Key::Unicode('5'), &["5"]),
+    entry(7, Key::Unicode('6'), &["6"]),
+    entry(8, Key::Unicode('7'), &["7"]),
+    entry(9, Key::Unicode('8'), &["8"]),
+    entry(10, Key::Unicode('9'), &["9"]),
+    entry(11, Key::Unicode('0'), &["0"]),
+    entry(12, Key::Unicode('-'), &["minus", "-"]),
+    entry(13, Key::Unicode('='), &["equal", "="]),
+    entry(14, Key::Backspace, &["backspace"]),
+    entry(15, Key::Tab, &["tab"]),
+    entry(16, Key::Unicode('q'), &["q"]),
+    entry(17, Key::Unicode('w'), &["w"]),
+    entry(18, Key::Unicode('e'), &["e"]),
+    entry(19, Key::Unicode('r'), &["r"]),
+    entry(20, Key::Unicode('t'), &["t"]),
+    entry(21, Key::Unicode('y'), &["y"]),
+    entry(22, Key::Unicode('u'), &["u"]),
+    entry(23, Key::Unicode('i'), &["i"]),
+    entry(24, Key::Unicode('o'), &["o"]),
+    entry(25, Key::Unicode('p'), &["p"]),
+    entry(26, Key::Unicode('['), &["leftbrace", "lbrace", "["]),
+    entry(27, Key::Unicode(']'), &["rightbrace", "rbrace", "]"]),
+    entry(28, Key::Return, &["return", "ret", "enter"]),
+    entry(29, Key::LControl, &["leftcontrol", "lcontrol", "leftctrl", "lctrl", "control", "ctrl"]),
+    entry(30, Key::Unicode('a'), &["a"]),
+    entry(31, Key::Unicode('s'), &["s"]),
+    entry(32, Key::Unicode('d'), &["d"]),
+    entry(33, Key::Unicode('f'), &["f"]),
+    entry(34, Key::Unicode('g'), &["g"]),
+    entry(35, Key::Unicode('h'), &["h"]),
+    entry(36, Key::Unicode('j'), &["j"]),
+    entry(37, Key::Unicode('k'), &["k"]),
+    entry(38, Key::Unicode('l'), &["l"]),
+    entry(39, Key::Unicode(';'), &["semicolon", ";"]),
+    entry(40, Key::Unicode('\''), &["apostrophe", "'"]),
+    entry(41, Key::Unicode('`'), &["grave", "tilde", "~"]),
+    entry(42, Key::LShift, &["leftshift", "lshift", "shift"]),
+    entry(43, Key::Unicode('\\'), &["backslash", "bslash", "\\"]),
+    entry(44, Key::Unicode('z'), &["z"]),
+    entry(45, Key::Unicode('x'), &["x"]),
+    entry(46, Key::Unicode('c'), &["c"]),
+    entry(47, Key::Unicode('v'), &["v"]),
+    entry(48, Key::Unicode('b'), &["b"]),
+    entry(49, Key::Unicode('n'), &["n"]),
+    entry(50, Key::Unicode('m'), &["m"]),
+    entry(51, Key::Unicode(','), &["comma", ","]),
+    entry(52, Key::Unicode('.'), &["decimal", "period", "dot", "."]),
+    entry(53, Key::Unicode('/'), &["forwardslash", "fslash", "slash", "/"]),
+    entry(54, Key::RShift, &["rightshift", "rshift"]),
+    entry(55, Key::Multiply, &["numasterisk", "asterisk", "num*", "multiply", "mult", "mul", "times"]),
+    entry(56, Key::Alt, &["leftalt", "lalt", "alt", "option"]),
+    entry(57, Key::Space, &["space"]),
+    entry(58, Key::CapsLock, &["capslock", "caps"]),
+    entry(59, Key::F1, &["f1"]),
+    entry(60, Key::F2, &["f2"]),
+    entry(61, Key::F3, &["f3"]),
+    entry(62, Key::F4, &["f4"]),
+    entry(63, Key::F5, &["f5"]),
+    entry(64, Key::F6, &["f6"]),
+    entry(65, Key::F7, &["f7"]),
+    entry(66, Key::F8, &["f8"]),
+    entry(67, Key::F9, &["f9"]),
+    entry(68, Key::F10, &["f10"]),
+    entry(69, Key::Numlock, &["numlock"]),
+    entry(70, Key::ScrollLock, &["scrolllock"]),
+    entry(71, Key::Numpad7, &["numseven", "num7"]),
+    entry(72, Key::Numpad8, &["numeight", "num8"]),
+    entry(73, Key::Numpad9, &["numnine", "num9"]),
+    entry(74, Key::Subtract, &["numminus", "num-", "subtract", "sub"]),
+    entry(75, Key::Numpad4, &["numfour", "num4"]),
+    entry(76, Key::Numpad5, &["numfive", "num5"]),
+    entry(77, Key::Numpad6, &["numsix", "num6"]),
+    entry(78, Key::Add, &["numadd", "num+", "add"]),
+    entry(79, Key::Numpad1, &["numone", "num1"]),
+    entry(80, Key::Numpad2, &["numtwo", "num2"]),
+    entry(81, Key::Numpad3, &["numthree", "num3"]),
+    entry(82, Key::Numpad0, &["numzero", "num0"]),
+    entry(83, Key::Decimal, &["numdot", "num."]),
+    code_only(85, &["zenkakukanakaku"]),
+    code_only(86, &["102nd"]),
+    entry(87, Key::F11, &["f11"]),
+    entry(88, Key::F12, &["f12"]),
+    code_only(89, &["ro"]),
+    code_only(90, &["katakana"]),
+    code_only(91, &["hiragana"]),
+    code_only(92, &["henkan"]),
+    code_only(93, &["katakanahiragana"]),
+    code_only(94, &["muhenkan"]),
+    code_only(95, &["numjapanesecomma", "numjcomma", "numj,"]),
+    code_only(96, &["numenter", "numreturn", "numret"]),
+    entry(97, Key::RControl, &["rightcontrol", "rcontrol", "rightctrl", "rctrl"]),
+    entry(98, Key::Divide, &["numslash", "num/", "divide"]),
+    entry(99, Key::SysReq, &["systemrequest", "sysrq", "sysreq"]),
+    entry(99, Key::PrintScr, &["printscreen", "printscr", "prtsc", "print"]),
+    code_only(100, &["rightalt", "ralt"]),
+    entry(101, Key::Linefeed, &["linefeed"]),
+    entry(102, Key::Home, &["home"]),
+    entry(103, Key::UpArrow, &["up", "uparrow"]),
+    entry(104, Key::PageUp, &["pageup", "pageu", "pgup"]),
+    entry(105, Key::LeftArrow, &["left", "leftarrow"]),
+    entry(106, Key::RightArrow, &["right", "rightarrow"]),
+    entry(107, Key::End, &["end"]),
+    entry(108, Key::DownArrow, &["down", "downarrow"]),
+    entry(109, Key::PageDown, &["pagedown", "pagedn", "paged", "pgdn"]),
+    entry(110, Key::Insert, &["insert", "ins"]),
+    entry(111, Key::Delete, &["delete", "del"]),
+    entry(113, Key::VolumeMute, &["mute", "volumemute"]),
+    entry(114, Key::VolumeDown, &["volumedown", "volumedn", "volumed", "voldown", "voldn", "vold"]),
+    entry(115, Key::VolumeUp, &["volumeup", "volumeu", "volup", "volu"]),
+    code_only(116, &["power"]),
+    code_only(117, &["numequal", "num="]),
+    entry(119, Key::Pause, &["pause"]),
+    entry(119, Key::Break, &["break"]),
+    code_only(121, &["numcomma", "num,"]),
+    entry(122, Key::Hangul, &["hanguel", "hangul"]),
+    entry(123, Key::Hanja, &["hanja"]),
+    code_only(124, &["yen"]),
+    entry(125, Key::Meta, &["leftmeta", "lmeta", "meta", "windows", "window", "win", "super", "command", "cmd"]),
+    code_only(126, &["rightmeta", "rmeta"]),
+    entry(127, Key::LMenu, &["compose", "leftmenu", "lmenu", "menu"]),
+    code_only(128, &["stop"]),
+    entry(129, Key::Redo, &["again", "redo"]),
+    code_only(130, &["props"]),
+    entry(131, Key::Undo, &["undo"]),
+    code_only(132, &["front"]),
+    code_only(133, &["copy"]),
+    code_only(134, &["open"]),
+    code_only(135, &["paste"]),
+    entry(136, Key::Find, &["find"]),
+    code_only(137, &["cut"]),
+    entry(138, Key::Help, &["help"]),
+    code_only(140, &["calc"]),
+    code_only(142, &["sleep"]),
+    code_only(150, &["www"]),
+    code_only(152, &["screenlock"]),
+    code_only(158, &["back"]),
+    code_only(159, &["forward"]),
+    code_only(161, &["ejectcd", "eject"]),
+    entry(163, Key::MediaNextTrack, &["nextsong", "next", "medianexttrack", "nexttrack"]),
+    entry(164, Key::MediaPlayPause, &["playpause", "mediaplaypause", "play"]),
+    entry(165, Key::MediaPrevTrack, &["previoussong", "prevsong", "previous", "prev", "mediaprevtrack", "prevtrack"]),
+    entry(166, Key::MediaStop, &["stopcd", "mediastop"]),
+    code_only(173, &["refresh"]),
+    code_only(176, &["edit"]),
+    code_only(177, &["scrollup"]),
+    code_only(178, &["scrolldown"]),
+    code_only(179, &["numleftparenthesis", "numlparenthesis", "num("]),
+    code_only(180, &["numrightparenthesis", "numrparenthesis", "num)"]),
+    entry(183, Key::F13, &["f13"]),
+    entry(184, Key::F14, &["f14"]),
+    entry(185, Key::F15, &["f15"]),
+    entry(186, Key::F16, &["f16"]),
+    entry(187, Key::F17, &["f17"]),
+    entry(188, Key::F18, &["f18"]),
+    entry(189, Key::F19, &["f19"]),
+    entry(190, Key::F20, &["f20"]),
+    entry(191, Key::F21, &["f21"]),
+    entry(192, Key::F22, &["f22"]),
+    entry(193, Key::F23, &["f23"]),
+    entry(194, Key::F24, &["f24"]),
+    entry(223, Key::Cancel, &["cancel"]),
+    entry(248, Key::MicMute, &["micmute"]),
+    entry(353, Key::Select, &["select", "sel"]),
+    entry(355, Key::Clear, &["clear", "clr"]),
+    key_only(Key::Begin, &["begin"]),
+    key_only(Key::Execute, &["execute", "exec"]),
+    key_only(Key::F25, &["f25"]),
+    key_only(Key::F26, &["f26"]),
+    key_only(Key::F27, &["f27"]),
+    key_only(Key::F28, &["f28"]),
+    key_only(Key::F29, &["f29"]),
+    key_only(Key::F30, &["f30"]),
+    key_only(Key::F31, &["f31"]),
+    key_only(Key::F32, &["f32"]),
+    key_only(Key::F33, &["f33"]),
+    key_only(Key::F34, &["f34"]),
+    key_only(Key::F35, &["f35"]),
+    key_only(Key::Kanji, &["kanji"]),
+    key_only(Key::ModeChange, &["modechange"]),
+    key_only(Key::ScriptSwitch, &["scriptswitch"]),
+    key_only(Key::ShiftLock, &["shiftlock"]),
+];
+
+pub fn from_name(name: &str) -> Option<&'static KeyInfo> {
+    let name = name.to_lowercase();
+
+    KEYS.iter().find(|info| info.names.contains(&name.as_str()))
+}
+
+/// Modifiers that can be written without a side, as triggers and conditions
+/// they match the left and right keys, and as actions they send the left one.
+/// The first name of each is the one used when writing scripts back out.
+pub static EITHER_SIDE: &[(&[&str], u16, u16)] = &[
+    (&["shift"], 42, 54),
+    (&["control", "ctrl"], 29, 97),
+    (&["alt", "option"], 56, 100),
+    (
+        &[
+            "meta", "windows", "window", "win", "super", "command", "cmd",
+        ],
+        125,
+        126,
+    ),
+];
+
+/// The left and right codes of a modifier written without a side.
+pub fn either_side(name: &str) -> Option<(u16, u16)> {
+    EITHER_SIDE
+        .iter()
+        .find(|(names, _, _)| names.contains(&name))
+        .map(|(_, left, right)| (*left, *right))
+}
+
+/// The name of a modifier without a side, from its left code.
+pub fn either_side_name(left: u16) -> Option<&'static str> {
+    EITHER_SIDE
+        .iter()
+        .find(|(_, code, _)| *code == left)
+        .map(|(names, _, _)| names[0])
+}
+
+pub fn from_code(code: u16) -> Option<&'static KeyInfo> {
+    KEYS.iter().find(|info| info.code == Some(code))
+}
+
+pub fn from_keysym(keysym: u32) -> Option<&'static KeyInfo> {
+    KEYS.iter().find(|info| {
+        info.key
+            .is_some_and(|key| Keysym::from(key).raw() == keysym)
+    })
+}
+
+/// Evdev codes of the named mouse buttons, the first name of each is the one
+/// used when writing scripts back out.
+pub static BUTTONS: &[(u32, &[&str])] = &[
+    (0x110, &["left"]),
+    (0x111, &["right"]),
+    (0x112, &["middle"]),
+    (0x113, &["side"]),
+    (0x114, &["extra"]),
+    (0x115, &["forward"]),
+    (0x116, &["back"]),
+    (0x117, &["task"]),
+];
+
+/// Numbered mouse triggers count up from here, so `mouse:1` is the left button.
+pub const BUTTON_OFFSET: u32 = 0x10f;
+
+pub fn button_from_name(name: &str) -> Option<u32> {
+    BUTTONS
+        .iter()
+        .find(|(_, names)| names.contains(&name))
+        .map(|(code, _)| *code)
+}
+
+pub fn button_name(code: u32) -> Option<&'static str> {
+    BUTTONS
+        .iter()
+        .find(|(button, _)| *button == code)
+        .map(|(_, names)| names[0])
+}
+
+impl KeyInfo {
+    pub fn name(&self) -> &'static str {
+        self.names[0]
+    }
+
+    /// What an `event` line using this key sends.
+    pub fn event_key(&self) -> Option<EventKey> {
+        self.key.map(EventKey::Key).or(self.code.map(EventKey::Raw))
+    }
+}
+
+impl EventKey {
+    /// The preferred name for the key, if it is in the table.
+    pub fn name(&self) -> Option<&'static str> {
+        KEYS.iter()
+            .find(|info| info.event_key() == Some(*self))
+            .map(KeyInfo::name)
+    }
+}
+
+impl fmt::Display for EventKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self, self.name()) {
+            (EventKey::Key(Key::Unicode(unicode)), _) => write!(f, "{unicode}"),
+            (_, Some(name)) => write!(f, "{name}"),
+            // Keys missing from the table are written as their keysym, which
+            // parses back to the same key
+            (EventKey::Key(key), None) => {
+                write!(f, "{:#x}", Keysym::from(*key).raw())
+            }
+            (EventKey::Raw(code), None) => write!(f, "#{code}"),
+        }
+    }
+}
@@ -1,3 +1,5 @@
+mod expr;
+mod keys;
 mod parser;
 mod runner;
 
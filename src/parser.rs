@@ -1,308 +1,489 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
 use enigo::{Direction, Key};
 
-#[derive(Debug)]
+use crate::expr::{Expr, Text};
+use crate::keys::{self, EventKey};
+
+#[derive(Debug, PartialEq)]
 pub enum Action {
-    KeyEvent { key: Key, direction: Direction },
-    MouseEvent { code: u16, direction: Direction },
-    Sleep(u64),
+    KeyEvent {
+        key: ActionKey,
+        direction: Direction,
+    },
+    MouseEvent {
+        code: u16,
+        direction: Direction,
+    },
+    /// Presses every key but the last in order, clicks the last and releases
+    /// the rest in reverse, waiting `delay` between each event.
+    Press {
+        keys: Vec<ActionKey>,
+        delay: Option<DurationValue>,
+    },
+    /// Presses a key or button and releases it after `duration`.
+    Hold {
+        input: Input,
+        duration: DurationValue,
+    },
+    /// Sleeps for `duration`, or a random time between `duration` and `max`.
+    Sleep {
+        duration: DurationValue,
+        max: Option<DurationValue>,
+    },
+    /// Sets a variable while the script runs.
+    Let {
+        name: String,
+        value: Expr,
+    },
+    Loop {
+        kind: LoopKind,
+        actions: Vec<Action>,
+    },
+    If {
+        condition: Condition,
+        actions: Vec<Action>,
+        else_actions: Vec<Action>,
+    },
+    /// Runs a macro, `args` set its parameters.
+    Call {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// A `#` line, kept so that formatting doesn't lose it.
+    Comment(String),
+}
+
+/// A key or mouse button for an action to press.
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    Key(ActionKey),
+    Mouse(u16),
+}
+
+/// A key in a trigger or condition. `alias` is the name it was written as,
+/// which is written back out in place of the key.
+#[derive(Debug, PartialEq)]
+pub struct TriggerKey {
+    pub code: u32,
+    /// The right key of a modifier written without a side, like `shift`,
+    /// either key counts.
+    pub right: Option<u32>,
+    pub alias: Option<String>,
+}
+
+impl TriggerKey {
+    pub fn new(code: u32) -> Self {
+        Self {
+            code,
+            right: None,
+            alias: None,
+        }
+    }
+
+    /// Whether a key with this evdev code counts as this key.
+    pub fn matches(&self, code: u32) -> bool {
+        self.code == code || self.right == Some(code)
+    }
+}
+
+/// A key for an action to send, with the alias it was written as.
+#[derive(Debug, PartialEq)]
+pub struct ActionKey {
+    pub key: EventKey,
+    pub alias: Option<String>,
+}
+
+/// A duration in an action.
+#[derive(Debug, PartialEq)]
+pub enum DurationValue {
+    Fixed(Duration),
+    /// A `${...}` expression of ms, worked out when the action runs.
+    Expr(Expr),
+}
+
+/// How many times a loop in an action list runs.
+#[derive(Debug, PartialEq)]
+pub enum LoopKind {
+    Count(Expr),
+    Forever,
+    /// Runs for as long as the condition holds, checked before each pass.
+    While(Condition),
+}
+
+/// Input state checked by `if` and `while`.
+#[derive(Debug, PartialEq)]
+pub enum Condition {
+    /// The block's own trigger is still held.
+    TriggerHeld,
+    KeyHeld(TriggerKey),
+    ButtonHeld(u32),
+    /// A lock key, like caps lock, is on.
+    Toggled(TriggerKey),
+}
+
+/// A `{` that hasn't been closed yet.
+enum Open {
+    Loop(LoopKind),
+    If(Condition),
+    /// The else branch of an `if`, with the actions of the first branch.
+    Else(Condition, Vec<Action>),
+    Macro {
+        comments: Vec<String>,
+        name: String,
+        params: Vec<String>,
+        /// How many variable reads there were before the macro started.
+        reads_start: usize,
+    },
 }
 
+#[derive(Debug, PartialEq)]
 pub enum Trigger {
+    /// An evdev button code.
     Mouse(u32),
-    Key(u32),
+    Key(TriggerKey),
+    /// A device matching the pattern was plugged in.
+    DeviceAdded(String),
+    /// Each step is a key press, the last code of a step is the key pressed
+    /// and any before it must be held down. Steps must come within `timeout`
+    /// of each other.
+    Sequence {
+        steps: Vec<Vec<TriggerKey>>,
+        timeout: Duration,
+    },
 }
 
-pub struct Script {
+/// Timing based variants of a trigger.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// Fires once the trigger has been held this long.
+    Hold(Duration),
+    /// Fires on the second press if it comes within this long of the first.
+    Double(Duration),
+    /// Fires on release if the trigger was held for less than this long.
+    Tap(Duration),
+}
+
+/// An `on` line and the actions that follow it.
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    /// Comment lines above the `on` line.
+    pub comments: Vec<String>,
     pub is_repeating: bool,
     pub trigger: Trigger,
+    /// Only accept the trigger from devices whose name, sysname or
+    /// `vendor:product` id matches this pattern.
+    pub device: Option<String>,
+    pub timing: Option<Timing>,
+    /// Every sleep is made up to this much shorter or longer.
+    pub jitter: Option<Duration>,
+    /// Clicks hold the key or button for a random time in this range.
+    pub click_time: Option<(Duration, Duration)>,
+    pub actions: Vec<Action>,
+    /// Actions run when the trigger is released.
+    pub release_actions: Vec<Action>,
+}
+
+/// Text that replaces an abbreviation as soon as it's typed.
+#[derive(Debug, PartialEq)]
+pub struct Hotstring {
+    pub comments: Vec<String>,
+    pub abbreviation: String,
+    pub replacement: Text,
+}
+
+impl Hotstring {
+    /// Characters that finish a word, typing one after an abbreviation
+    /// expands it.
+    pub fn is_end_char(c: char) -> bool {
+        c == ' ' || "-()[]{}':;\"/\\,.?!".contains(c)
+    }
+}
+
+/// A list of actions that hotkeys can run with `call`. Parameters are
+/// variables that are set for the length of the call.
+#[derive(Debug, PartialEq)]
+pub struct Macro {
+    pub comments: Vec<String>,
+    pub name: String,
+    pub params: Vec<String>,
     pub actions: Vec<Action>,
 }
 
+/// An `include` line, `script` is `None` when the file was already included
+/// somewhere else.
+#[derive(Debug, PartialEq)]
+pub struct Include {
+    pub path: String,
+    pub script: Option<Script>,
+}
+
+/// A top level line that the lines after it, and the files included after
+/// it, can use.
+#[derive(Debug, PartialEq)]
+pub enum Declaration {
+    Include(Include),
+    /// A `let` line from before the first hotkey.
+    Constant(String, Expr),
+    /// An `alias` line, the name and the key it stands for.
+    Alias(String, String),
+    /// A comment line above one of the others.
+    Comment(String),
+}
+
+/// A parsed script. Comment lines are kept with the line below them, so they
+/// stay in place when the script is written back out.
+#[derive(Debug, PartialEq)]
+pub struct Script {
+    /// Comment lines above the `seat` line.
+    pub seat_comments: Vec<String>,
+    pub seat: Option<String>,
+    /// `include`, constant and `alias` lines in the order they were written.
+    pub declarations: Vec<Declaration>,
+    pub hotstrings: Vec<Hotstring>,
+    pub macros: Vec<Macro>,
+    pub blocks: Vec<Block>,
+    /// Comment lines after everything else.
+    pub end_comments: Vec<String>,
+}
+
+/// Splits a line on whitespace, keeping quoted strings and `${...}`
+/// expressions together.
+fn split_words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut in_expr = false;
+
+    for (index, c) in line.char_indices() {
+        if c.is_whitespace() && !quoted && !in_expr {
+            if let Some(start) = start.take() {
+                words.push(&line[start..index]);
+            }
+            continue;
+        }
+
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == '{' && line[..index].ends_with('$') {
+            in_expr = true;
+        } else if c == '}' {
+            in_expr = false;
+        }
+        start.get_or_insert(index);
+    }
+
+    if let Some(start) = start {
+        words.push(&line[start..]);
+    }
+
+    words
+}
+
+fn parse_keysym(key_str: &str) -> Option<u32> {
+    u32::from_str_radix(key_str.strip_prefix("0x")?, 16).ok()
+}
+
+/// The key an alias stands for along with the alias, or the key itself.
+fn resolve_alias<'a>(
+    key_str: &'a str,
+    aliases: &'a HashMap<String, String>,
+) -> (&'a str, Option<String>) {
+    match aliases.get(key_str) {
+        Some(key) => (key, Some(key_str.to_string())),
+        None => (key_str, None),
+    }
+}
+
 fn parse_trigger_key_string(key_str: &str) -> Option<u16> {
-    match key_str.to_lowercase().as_str() {
-        "escape" | "esc" => Some(1),
-        "1" => Some(2),
-        "2" => Some(3),
-        "3" => Some(4),
-        "4" => Some(5),
-        "5" => Some(6),
-        "6" => Some(7),
-        "7" => Some(8),
-        "8" => Some(9),
-        "9" => Some(10),
-        "0" => Some(11),
-        "minus" | "-" => Some(12),
-        "equal" | "=" => Some(13),
-        "backspace" => Some(14),
-        "tab" => Some(15),
-        "q" => Some(16),
-        "w" => Some(17),
-        "e" => Some(18),
-        "r" => Some(19),
-        "t" => Some(20),
-        "y" => Some(21),
-        "u" => Some(22),
-        "i" => Some(23),
-        "o" => Some(24),
-        "p" => Some(25),
-        "leftbrace" | "lbrace" | "[" => Some(26),
-        "rightbrace" | "rbrace" | "]" => Some(27),
-        "return" | "ret" | "enter" => Some(28),
-        "leftcontrol" | "lcontrol" | "leftctrl" | "lctrl" => Some(29),
-        "a" => Some(30),
-        "s" => Some(31),
-        "d" => Some(32),
-        "f" => Some(33),
-        "g" => Some(34),
-        "h" => Some(35),
-        "j" => Some(36),
-        "k" => Some(37),
-        "l" => Some(38),
-        "semicolon" | ";" => Some(39),
-        "apostrophe" | "'" => Some(40),
-        "grave" | "tilde" | "~" => Some(41),
-        "leftshift" | "lshift" => Some(42),
-        "backslash" | "bslash" | "\\" => Some(43),
-        "z" => Some(44),
-        "x" => Some(45),
-        "c" => Some(46),
-        "v" => Some(47),
-        "b" => Some(48),
-        "n" => Some(49),
-        "m" => Some(50),
-        "comma" | "," => Some(51),
-        "decimal" | "period" | "dot" | "." => Some(52),
-        "forwardslash" | "fslash" | "slash" | "/" => Some(53),
-        "rightshift" | "rshift" => Some(54),
-        "numasterisk" | "asterisk" | "num*" => Some(55),
-        "leftalt" | "lalt" => Some(56),
-        "space" => Some(57),
-        "capslock" | "caps" => Some(58),
-        "f1" => Some(59),
-        "f2" => Some(60),
-        "f3" => Some(61),
-        "f4" => Some(62),
-        "f5" => Some(63),
-        "f6" => Some(64),
-        "f7" => Some(65),
-        "f8" => Some(66),
-        "f9" => Some(67),
-        "f10" => Some(68),
-        "numlock" => Some(69),
-        "scrolllock" => Some(70),
-        "numseven" | "num7" => Some(71),
-        "numeight" | "num8" => Some(72),
-        "numnine" | "num9" => Some(73),
-        "numminus" | "num-" => Some(74),
-        "numfour" | "num4" => Some(75),
-        "numfive" | "num5" => Some(76),
-        "numsix" | "num6" => Some(77),
-        "numadd" | "num+" => Some(78),
-        "numone" | "num1" => Some(79),
-        "numtwo" | "num2" => Some(80),
-        "numthree" | "num3" => Some(81),
-        "numzero" | "num0" => Some(82),
-        "numdot" | "num." => Some(83),
-        "zenkakukanakaku" => Some(85),
-        "102nd" => Some(86),
-        "f11" => Some(87),
-        "f12" => Some(88),
-        "ro" => Some(89),
-        "katakana" => Some(90),
-        "hiragana" => Some(91),
-        "henkan" => Some(92),
-        "katakanahiragana" => Some(93),
-        "muhenkan" => Some(94),
-        "numjapanesecomma" | "numjcomma" | "numj," => Some(95),
-        "numenter" | "numreturn" | "numret" => Some(96),
-        "rightcontrol" | "rcontrol" | "rightctrl" | "rctrl" => Some(97),
-        "numslash" | "num/" => Some(98),
-        "systemrequest" | "sysrq" => Some(99),
-        "rightalt" | "ralt" => Some(100),
-        "home" => Some(102),
-        "up" => Some(103),
-        "pageup" | "pageu" => Some(104),
-        "left" => Some(105),
-        "right" => Some(106),
-        "end" => Some(107),
-        "down" => Some(108),
-        "pagedown" | "pagedn" | "paged" => Some(109),
-        "insert" | "ins" => Some(110),
-        "delete" | "del" => Some(111),
-        "mute" => Some(113),
-        "volumedown" | "volumedn" | "volumed" | "voldown" | "voldn" | "vold" => Some(114),
-        "volumeup" | "volumeu" | "volup" | "volu" => Some(115),
-        "power" => Some(116),
-        "numequal" | "num=" => Some(117),
-        "pause" => Some(119),
-        "numcomma" | "num," => Some(121),
-        "hanguel" => Some(122),
-        "hanja" => Some(123),
-        "yen" => Some(124),
-        "leftmeta" | "lmeta" | "meta" | "windows" | "window" | "win" | "super" | "command"
-        | "cmd" => Some(125),
-        "rightmeta" | "rmeta" => Some(126),
-        "compose" => Some(127),
-        "stop" => Some(128),
-        "again" => Some(129),
-        "props" => Some(130),
-        "undo" => Some(131),
-        "front" => Some(132),
-        "copy" => Some(133),
-        "open" => Some(134),
-        "paste" => Some(135),
-        "find" => Some(136),
-        "cut" => Some(137),
-        "help" => Some(138),
-        "calc" => Some(140),
-        "sleep" => Some(142),
-        "www" => Some(150),
-        "screenlock" => Some(152),
-        "back" => Some(158),
-        "forward" => Some(159),
-        "ejectcd" | "eject" => Some(161),
-        "nextsong" | "next" => Some(163),
-        "playpause" => Some(164),
-        "previoussong" | "prevsong" | "previous" | "prev" => Some(165),
-        "stopcd" => Some(166),
-        "refresh" => Some(173),
-        "edit" => Some(176),
-        "scrollup" => Some(177),
-        "scrolldown" => Some(178),
-        "numleftparenthesis" | "numlparenthesis" | "num(" => Some(179),
-        "numrightparenthesis" | "numrparenthesis" | "num)" => Some(180),
-        "f13" => Some(183),
-        "f14" => Some(184),
-        "f15" => Some(185),
-        "f16" => Some(186),
-        "f17" => Some(187),
-        "f18" => Some(188),
-        "f19" => Some(189),
-        "f20" => Some(190),
-        "f21" => Some(191),
-        "f22" => Some(192),
-        "f23" => Some(193),
-        "f24" => Some(194),
-        _ => None,
+    if let Some(code) = key_str.strip_prefix('#') {
+        return code.parse().ok().filter(|code| *code <= keys::KEY_MAX);
+    }
+
+    let info = match parse_keysym(key_str) {
+        Some(keysym) => keys::from_keysym(keysym),
+        None => keys::from_name(key_str),
+    };
+
+    info.and_then(|info| info.code)
+}
+
+/// Parses a mouse trigger into an evdev button code.
+fn parse_trigger_button_string(button_str: &str) -> Option<u32> {
+    if let Some(code) = button_str.strip_prefix('#') {
+        return parse_keysym(code).or_else(|| code.parse().ok());
+    }
+
+    if let Ok(number) = button_str.parse::<u32>() {
+        return number.checked_add(keys::BUTTON_OFFSET);
+    }
+
+    keys::button_from_name(button_str)
+}
+
+/// Parses a key of a trigger or condition, which may be an alias.
+fn parse_trigger_key(key_str: &str, aliases: &HashMap<String, String>) -> Option<TriggerKey> {
+    let (key_str, alias) = resolve_alias(key_str, aliases);
+
+    if let Some((left, right)) = keys::either_side(key_str) {
+        return Some(TriggerKey {
+            code: left.into(),
+            right: Some(right.into()),
+            alias,
+        });
     }
+
+    Some(TriggerKey {
+        code: parse_trigger_key_string(key_str)?.into(),
+        right: None,
+        alias,
+    })
 }
 
-fn parse_action_key_string(key_str: &str) -> Option<Key> {
+/// Parses a key for an action to send, which may be an alias.
+fn parse_action_key(key_str: &str, aliases: &HashMap<String, String>) -> Option<ActionKey> {
     let key_str = key_str.trim().to_lowercase();
+    let (key_str, alias) = resolve_alias(&key_str, aliases);
+
+    Some(ActionKey {
+        key: parse_action_key_string(key_str)?,
+        alias,
+    })
+}
 
+fn parse_action_key_string(key_str: &str) -> Option<EventKey> {
     let mut iter = key_str.chars();
     if let (Some(unicode), None) = (iter.next(), iter.next()) {
-        return Some(Key::Unicode(unicode));
-    }
-
-    match key_str.as_str() {
-        "add" => Some(Key::Add),
-        "alt" => Some(Key::Alt),
-        "backspace" => Some(Key::Backspace),
-        "break" => Some(Key::Break),
-        "begin" => Some(Key::Begin),
-        "cancel" => Some(Key::Cancel),
-        "capslock" => Some(Key::CapsLock),
-        "clear" | "clr" => Some(Key::Clear),
-        "command" | "cmd" | "super" | "windows" | "win" | "meta" => Some(Key::Meta),
-        "control" | "ctrl" => Some(Key::Control),
-        "decimal" | "period" | "dot" => Some(Key::Decimal),
-        "delete" | "del" => Some(Key::Delete),
-        "divide" => Some(Key::Divide),
-        "downarrow" | "down" => Some(Key::DownArrow),
-        "end" => Some(Key::End),
-        "escape" | "esc" => Some(Key::Escape),
-        "execute" | "exec" => Some(Key::Execute),
-        "f1" => Some(Key::F1),
-        "f2" => Some(Key::F2),
-        "f3" => Some(Key::F3),
-        "f4" => Some(Key::F4),
-        "f5" => Some(Key::F5),
-        "f6" => Some(Key::F6),
-        "f7" => Some(Key::F7),
-        "f8" => Some(Key::F8),
-        "f9" => Some(Key::F9),
-        "f10" => Some(Key::F10),
-        "f11" => Some(Key::F11),
-        "f12" => Some(Key::F12),
-        "f13" => Some(Key::F13),
-        "f14" => Some(Key::F14),
-        "f15" => Some(Key::F15),
-        "f16" => Some(Key::F16),
-        "f17" => Some(Key::F17),
-        "f18" => Some(Key::F18),
-        "f19" => Some(Key::F19),
-        "f20" => Some(Key::F20),
-        "f21" => Some(Key::F21),
-        "f22" => Some(Key::F22),
-        "f23" => Some(Key::F23),
-        "f24" => Some(Key::F24),
-        "f25" => Some(Key::F25),
-        "f26" => Some(Key::F26),
-        "f27" => Some(Key::F27),
-        "f28" => Some(Key::F28),
-        "f29" => Some(Key::F29),
-        "f30" => Some(Key::F30),
-        "f31" => Some(Key::F31),
-        "f32" => Some(Key::F32),
-        "f33" => Some(Key::F33),
-        "f34" => Some(Key::F34),
-        "f35" => Some(Key::F35),
-        "find" => Some(Key::Find),
-        "hangul" => Some(Key::Hangul),
-        "hanja" => Some(Key::Hanja),
-        "help" => Some(Key::Help),
-        "home" => Some(Key::Home),
-        "insert" | "ins" => Some(Key::Insert),
-        "kanji" => Some(Key::Kanji),
-        "leftcontrol" | "lcontrol" | "leftctrl" | "lctrl" => Some(Key::LControl),
-        "leftarrow" | "left" => Some(Key::LeftArrow),
-        "linefeed" => Some(Key::Linefeed),
-        "leftmenu" | "lmenu" => Some(Key::LMenu),
-        "leftshift" | "lshift" => Some(Key::LShift),
-        "medianexttrack" | "nexttrack" | "next" => Some(Key::MediaNextTrack),
-        "mediaplaypause" | "playpause" | "play" => Some(Key::MediaPlayPause),
-        "mediaprevtrack" | "prevtrack" | "prev" => Some(Key::MediaPrevTrack),
-        "mediastop" | "stop" => Some(Key::MediaStop),
-        "modechange" => Some(Key::ModeChange),
-        "multiply" | "mult" | "mul" | "times" => Some(Key::Multiply),
-        "numlock" => Some(Key::Numlock),
-        "numzero" | "num0" => Some(Key::Numpad0),
-        "numone" | "num1" => Some(Key::Numpad1),
-        "numtwo" | "num2" => Some(Key::Numpad2),
-        "numthree" | "num3" => Some(Key::Numpad3),
-        "numfour" | "num4" => Some(Key::Numpad4),
-        "numfive" | "num5" => Some(Key::Numpad5),
-        "numsix" | "num6" => Some(Key::Numpad6),
-        "numseven" | "num7" => Some(Key::Numpad7),
-        "numeight" | "num8" => Some(Key::Numpad8),
-        "numnine" | "num9" => Some(Key::Numpad9),
-        "option" => Some(Key::Option),
-        "pagedown" | "pgdn" => Some(Key::PageDown),
-        "pageup" | "pgup" => Some(Key::PageUp),
-        "pause" => Some(Key::Pause),
-        "printscr" | "prtsc" | "printscreen" | "print" => Some(Key::PrintScr),
-        "rightcontrol" | "rcontrol" | "rightctrl" | "rctrl" => Some(Key::RControl),
-        "redo" => Some(Key::Redo),
-        "return" | "ret" | "enter" => Some(Key::Return),
-        "rightarrow" | "right" => Some(Key::RightArrow),
-        "rightshift" | "rshift" => Some(Key::RShift),
-        "scrolllock" => Some(Key::ScrollLock),
-        "select" | "sel" => Some(Key::Select),
-        "scriptswitch" => Some(Key::ScriptSwitch),
-        "shift" => Some(Key::Shift),
-        "shiftlock" => Some(Key::ShiftLock),
-        "space" => Some(Key::Space),
-        "subtract" | "sub" | "minus" => Some(Key::Subtract),
-        "systemrequest" | "sysreq" => Some(Key::SysReq),
-        "tab" => Some(Key::Tab),
-        "undo" => Some(Key::Undo),
-        "uparrow" | "up" => Some(Key::UpArrow),
-        "volumedown" | "voldown" | "voldn" => Some(Key::VolumeDown),
-        "volumemute" | "mute" => Some(Key::VolumeMute),
-        "volumeup" | "volup" => Some(Key::VolumeUp),
-        "micmute" => Some(Key::MicMute),
-        _ => None,
+        return Some(EventKey::Key(Key::Unicode(unicode)));
+    }
+
+    if let Some(code) = key_str.strip_prefix('#') {
+        return code
+            .parse()
+            .ok()
+            .filter(|code| *code <= keys::KEY_MAX)
+            .map(EventKey::Raw);
+    }
+
+    if let Some(keysym) = parse_keysym(key_str) {
+        return Some(EventKey::Key(Key::Other(keysym)));
+    }
+
+    keys::from_name(key_str).and_then(|info| info.event_key())
+}
+
+/// Parses a number, or an expression wrapped in `${...}`. Expressions that
+/// only use constants must be possible to evaluate.
+fn parse_value(value_str: &str, constants: &HashMap<String, i64>) -> Option<Expr> {
+    let expr = match value_str
+        .strip_prefix("${")
+        .and_then(|expr| expr.strip_suffix('}'))
+    {
+        Some(expr) => Expr::parse(expr, constants)?,
+        None => Expr::Number(value_str.parse().ok()?),
+    };
+
+    (!expr.variables().is_empty() || expr.constant().is_some()).then_some(expr)
+}
+
+/// The longest duration a script can use, anything longer is a typo and
+/// would overflow once added to the current time.
+pub const MAX_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Parses a duration like `250ms`, `1.5s`, `2m` or `300us`, plain numbers
+/// are ms. Durations are rounded to the nearest us, and can't be longer than
+/// `MAX_DURATION`.
+fn parse_duration(duration_str: &str) -> Option<Duration> {
+    let (number, micros) = [("us", 1.0), ("ms", 1e3), ("s", 1e6), ("m", 60e6)]
+        .into_iter()
+        .find_map(|(unit, micros)| Some((duration_str.strip_suffix(unit)?, micros)))
+        .unwrap_or((duration_str, 1e3));
+
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+
+    let number: f64 = number.parse().ok()?;
+    let micros = (number * micros).round();
+    (micros <= MAX_DURATION.as_micros() as f64).then(|| Duration::from_micros(micros as u64))
+}
+
+/// Splits `min..max` into its two ends, a `min` without a unit takes the
+/// unit of `max`, so `1..2s` is one to two seconds.
+fn split_range(range_str: &str) -> (String, Option<&str>) {
+    let Some((min, max)) = range_str.split_once("..") else {
+        return (range_str.to_string(), None);
+    };
+
+    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+    let unit = max.trim_start_matches(is_number);
+    if !max.starts_with("${") && min.chars().all(is_number) {
+        (format!("{min}{unit}"), Some(max))
+    } else {
+        (min.to_string(), Some(max))
+    }
+}
+
+/// Parses `20..40ms` or a single duration, which is a range of one value.
+fn parse_duration_range(range_str: &str) -> Option<(Duration, Duration)> {
+    let (min, max) = split_range(range_str);
+    let min = parse_duration(&min)?;
+    let max = max.map_or(Some(min), parse_duration)?;
+
+    (min <= max).then_some((min, max))
+}
+
+/// Parses a duration, or an expression of ms wrapped in `${...}`.
+fn parse_duration_value(
+    value_str: &str,
+    constants: &HashMap<String, i64>,
+) -> Option<DurationValue> {
+    if value_str.starts_with("${") {
+        parse_value(value_str, constants).map(DurationValue::Expr)
+    } else {
+        parse_duration(value_str).map(DurationValue::Fixed)
+    }
+}
+
+/// Writes a duration in the largest unit that keeps it a whole number.
+fn write_duration(f: &mut fmt::Formatter, duration: Duration) -> fmt::Result {
+    let micros = duration.as_micros();
+
+    match micros {
+        0 => write!(f, "0ms"),
+        _ if micros.is_multiple_of(60_000_000) => write!(f, "{}m", micros / 60_000_000),
+        _ if micros.is_multiple_of(1_000_000) => write!(f, "{}s", micros / 1_000_000),
+        _ if micros.is_multiple_of(1_000) => write!(f, "{}ms", micros / 1_000),
+        _ => write!(f, "{micros}us"),
+    }
+}
+
+/// Parses the `key:code` or `mouse:code` of an `event` or `hold` line.
+fn parse_input(
+    input_str: &str,
+    aliases: &HashMap<String, String>,
+    line_number: LineNumber,
+) -> Input {
+    let (input_type, input_value) = input_str
+        .split_once(':')
+        .unwrap_or_else(|| panic!("No action value passed. Line {line_number}"));
+
+    match input_type {
+        "key" | "k" => Input::Key(
+            parse_action_key(input_value, aliases)
+                .unwrap_or_else(|| panic!("Could not parse action value. Line {line_number}")),
+        ),
+        "mouse" | "m" => Input::Mouse(
+            input_value
+                .parse()
+                .unwrap_or_else(|_| panic!("Could not parse action value: Line {line_number}")),
+        ),
+        _ => panic!("Could not parse action type. Line {line_number}"),
     }
 }
 
@@ -315,135 +496,1945 @@ fn parse_direction(dir_str: &str) -> Option<Direction> {
     }
 }
 
-impl Script {
-    pub fn read(script_path: String) -> Self {
-        let mut is_repeating = false;
-        let mut trigger: Option<Trigger> = None;
-        let mut actions: Vec<Action> = Vec::new();
+fn direction_name(direction: &Direction) -> &'static str {
+    match direction {
+        Direction::Press => "press",
+        Direction::Release => "release",
+        Direction::Click => "click",
+    }
+}
 
-        let file = std::fs::read_to_string(&script_path)
-            .expect(format!("File not found: {}", script_path).as_str());
-        let lines = file
-            .lines()
-            .map(str::trim)
-            .map(str::to_lowercase)
-            .into_iter();
-
-        for (line_number, line) in lines.enumerate() {
-            let line_number = line_number + 1;
-            if line.len() == 0 || line.starts_with('#') {
-                continue;
-            }
+fn write_trigger_key(f: &mut fmt::Formatter, code: u32) -> fmt::Result {
+    match u16::try_from(code).ok().and_then(keys::from_code) {
+        Some(info) => write!(f, "{}", info.name()),
+        None => write!(f, "#{code}"),
+    }
+}
 
-            let mut words = line.split_whitespace();
-            let operation = words
-                .next()
-                .expect(&format!("No operation on line: {line_number}"));
+impl fmt::Display for TriggerKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let either_side = self
+            .right
+            .and_then(|_| u16::try_from(self.code).ok())
+            .and_then(keys::either_side_name);
 
-            match operation {
-                "on" => {
-                    let mut action_string = words
-                        .next()
-                        .expect(&format!("No trigger passed. Line {line_number}"))
-                        .split(':');
+        match (&self.alias, either_side) {
+            (Some(alias), _) => write!(f, "{alias}"),
+            (None, Some(name)) => write!(f, "{name}"),
+            (None, None) => write_trigger_key(f, self.code),
+        }
+    }
+}
 
-                    let action_type = action_string
-                        .next()
-                        .expect(&format!("No action type passed. Line {line_number}"));
-                    let action_value = action_string
-                        .next()
-                        .expect(&format!("No action value passed. Line {line_number}"));
-
-                    trigger =
-                        Some(match action_type {
-                            "key" | "k" => {
-                                Trigger::Key(parse_trigger_key_string(action_value).expect(
-                                    &format!("Could not parse action value. Line {line_number}"),
-                                ) as u32)
-                            }
-                            "mouse" | "m" => Trigger::Mouse(action_value.parse::<u32>().expect(
-                                &format!("Could not parse action value. Line {line_number}"),
-                            )),
-                            _ => panic!("Could not parse action type. Line {line_number}"),
-                        });
+impl fmt::Display for ActionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.alias {
+            Some(alias) => write!(f, "{alias}"),
+            None => write!(f, "{}", self.key),
+        }
+    }
+}
 
-                    is_repeating = match words.next().unwrap_or("") {
-                        "repeating" | "repeat" | "r" => true,
-                        _ => false,
-                    };
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trigger::Key(key) => write!(f, "key:{key}"),
+            Trigger::Mouse(code) => match keys::button_name(*code) {
+                Some(name) => write!(f, "mouse:{name}"),
+                None => write!(f, "mouse:#{code:#x}"),
+            },
+            Trigger::DeviceAdded(pattern) => write!(f, "device_added:\"{pattern}\""),
+            Trigger::Sequence { steps, .. } => {
+                write!(f, "seq:")?;
+
+                for (index, step) in steps.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+
+                    for (index, key) in step.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, "+")?;
+                        }
+                        write!(f, "{key}")?;
+                    }
                 }
-                "event" => {
-                    let mut action_string = words
-                        .next()
-                        .expect(&format!("No trigger passed. Line {line_number}"))
-                        .split(':');
 
-                    let action_type = action_string
-                        .next()
-                        .expect(&format!("No action type passed. Line {line_number}"));
-                    let action_value = action_string
-                        .next()
-                        .expect(&format!("No action value passed. Line {line_number}"));
-                    let action_direction = words.next();
+                Ok(())
+            }
+        }
+    }
+}
 
-                    match action_type.to_lowercase().as_str() {
-                        "key" | "k" => {
-                            let key = parse_action_key_string(action_value).expect(&format!(
-                                "Could not parse action value. Line {line_number}"
-                            ));
-
-                            let direction = action_direction
-                                .map(|ad| {
-                                    parse_direction(ad).expect(&format!(
-                                        "Could not parse direction. Line {line_number}"
-                                    ))
-                                })
-                                .unwrap_or(Direction::Click);
+/// Writes the actions inside a `{ ... }`, indented one level.
+fn write_nested(f: &mut fmt::Formatter, actions: &[Action]) -> fmt::Result {
+    for action in actions {
+        for line in action.to_string().lines() {
+            writeln!(f, "    {line}")?;
+        }
+    }
 
-                            let action = Action::KeyEvent { key, direction };
+    Ok(())
+}
 
-                            actions.push(action);
-                        }
-                        "mouse" | "m" => {
-                            let code = action_value.parse::<u16>().expect(&format!(
-                                "Could not parse action value: Line {line_number}"
-                            ));
-
-                            let direction = action_direction
-                                .map(|ad| {
-                                    parse_direction(ad).expect(&format!(
-                                        "Could not parse direction. Line {line_number}"
-                                    ))
-                                })
-                                .unwrap_or(Direction::Click);
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Condition::TriggerHeld => write!(f, "held"),
+            Condition::KeyHeld(key) => write!(f, "held key:{key}"),
+            Condition::ButtonHeld(code) => write!(f, "held {}", Trigger::Mouse(*code)),
+            Condition::Toggled(key) => write!(f, "toggled {key}"),
+        }
+    }
+}
 
-                            let action = Action::MouseEvent { code, direction };
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::KeyEvent { key, direction } => {
+                write!(f, "event key:{key} {}", direction_name(direction))
+            }
+            Action::MouseEvent { code, direction } => {
+                write!(f, "event mouse:{code} {}", direction_name(direction))
+            }
+            Action::Press { keys, delay } => {
+                write!(f, "press ")?;
+                for (i, key) in keys.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "+")?;
+                    }
+                    write!(f, "{key}")?;
+                }
 
-                            actions.push(action);
-                        }
-                        _ => panic!("Could not parse action type. Line {line_number}"),
+                if let Some(delay) = delay {
+                    write!(f, " delay={delay}")?;
+                }
+
+                Ok(())
+            }
+            Action::Hold { input, duration } => write!(f, "hold {input} {duration}"),
+            Action::Sleep { duration, max } => {
+                write!(f, "sleep {duration}")?;
+
+                if let Some(max) = max {
+                    write!(f, "..{max}")?;
+                }
+
+                Ok(())
+            }
+            Action::Let { name, value } => write!(f, "let {name} = {value}"),
+            Action::Loop { kind, actions } => {
+                match kind {
+                    LoopKind::Count(count) => {
+                        write!(f, "repeat ")?;
+                        count.write_interpolated(f)?;
+                        writeln!(f, " {{")?;
                     }
+                    LoopKind::Forever => writeln!(f, "loop {{")?,
+                    LoopKind::While(condition) => writeln!(f, "while {condition} {{")?,
                 }
-                "sleep" => {
-                    let duration_string = words
-                        .next()
-                        .expect(format!("No duration passed. Line {line_number}").as_str());
-                    let duration = duration_string
-                        .parse::<u64>()
-                        .expect(format!("Could not parse duration. Line {line_number}").as_str());
 
-                    let action = Action::Sleep(duration);
+                write_nested(f, actions)?;
+                write!(f, "}}")
+            }
+            Action::If {
+                condition,
+                actions,
+                else_actions,
+            } => {
+                writeln!(f, "if {condition} {{")?;
+                write_nested(f, actions)?;
+
+                if !else_actions.is_empty() {
+                    writeln!(f, "}} else {{")?;
+                    write_nested(f, else_actions)?;
+                }
 
-                    actions.push(action);
+                write!(f, "}}")
+            }
+            Action::Call { name, args } => {
+                write!(f, "call {name}")?;
+                for arg in args {
+                    write!(f, " ")?;
+                    arg.write_interpolated(f)?;
                 }
-                _ => panic!("Could not parse operation. Line {line_number}"),
+
+                Ok(())
             }
+            Action::Comment(text) => write!(f, "{text}"),
         }
+    }
+}
 
-        Self {
-            is_repeating,
-            trigger: trigger.expect("No trigger set."),
-            actions,
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Input::Key(key) => write!(f, "key:{key}"),
+            Input::Mouse(code) => write!(f, "mouse:{code}"),
+        }
+    }
+}
+
+impl fmt::Display for DurationValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DurationValue::Fixed(duration) => write_duration(f, *duration),
+            DurationValue::Expr(expr) => write!(f, "${{{expr}}}"),
+        }
+    }
+}
+
+/// Writes comment lines, each on its own line.
+fn write_comments(f: &mut fmt::Formatter, comments: &[String]) -> fmt::Result {
+    for comment in comments {
+        writeln!(f, "{comment}")?;
+    }
+
+    Ok(())
+}
+
+impl fmt::Display for Macro {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_comments(f, &self.comments)?;
+        write!(f, "macro {}", self.name)?;
+        for param in &self.params {
+            write!(f, " {param}")?;
+        }
+        writeln!(f, " {{")?;
+
+        write_nested(f, &self.actions)?;
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_comments(f, &self.comments)?;
+        let mode = if self.is_repeating { "repeat" } else { "once" };
+        write!(f, "on {} {mode}", self.trigger)?;
+
+        if let Trigger::Sequence { timeout, .. } = self.trigger {
+            write!(f, " timeout=")?;
+            write_duration(f, timeout)?;
+        }
+
+        let timing = match self.timing {
+            Some(Timing::Hold(duration)) => Some(("hold", duration)),
+            Some(Timing::Double(duration)) => Some(("double", duration)),
+            Some(Timing::Tap(duration)) => Some(("tap", duration)),
+            None => None,
+        };
+        if let Some((kind, duration)) = timing {
+            write!(f, " {kind}=")?;
+            write_duration(f, duration)?;
+        }
+
+        if let Some(jitter) = self.jitter {
+            write!(f, " jitter=")?;
+            write_duration(f, jitter)?;
+        }
+        if let Some((min, max)) = self.click_time {
+            write!(f, " click_time=")?;
+            write_duration(f, min)?;
+            if min != max {
+                write!(f, "..")?;
+                write_duration(f, max)?;
+            }
+        }
+
+        // Repeating and hold blocks always use sections, on_release can't
+        // repeat or have timing
+        let sections = !self.release_actions.is_empty()
+            && (!self.actions.is_empty() || self.is_repeating || self.timing.is_some());
+        if !sections && !self.release_actions.is_empty() {
+            write!(f, " on_release")?;
+        }
+
+        if let Some(device) = &self.device {
+            write!(f, " device=\"{device}\"")?;
+        }
+        writeln!(f)?;
+
+        if !self.actions.is_empty() || !self.release_actions.is_empty() {
+            writeln!(f)?;
+        }
+
+        if sections {
+            writeln!(f, "press:")?;
+        }
+        for action in &self.actions {
+            writeln!(f, "{action}")?;
+        }
+
+        if sections {
+            writeln!(f, "release:")?;
+        }
+        for action in &self.release_actions {
+            writeln!(f, "{action}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Hotstring {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_comments(f, &self.comments)?;
+        write!(
+            f,
+            "hotstring \"{}\" -> \"{}\"",
+            self.abbreviation, self.replacement
+        )
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separate = false;
+
+        if let Some(seat) = &self.seat {
+            write_comments(f, &self.seat_comments)?;
+            writeln!(f, "seat {seat}")?;
+            separate = true;
+        }
+
+        // Kept in order as included files can use the constants and aliases
+        // before them, a blank line goes between each run of one kind, and
+        // comments go with the declaration below them
+        let mut previous = None;
+        for (index, declaration) in self.declarations.iter().enumerate() {
+            let kind = self.declarations[index..]
+                .iter()
+                .find(|declaration| !matches!(declaration, Declaration::Comment(_)))
+                .map(std::mem::discriminant);
+            if separate && previous != kind {
+                writeln!(f)?;
+            }
+            previous = kind;
+
+            match declaration {
+                Declaration::Include(include) => writeln!(f, "include \"{}\"", include.path)?,
+                Declaration::Constant(name, value) => writeln!(f, "let {name} = {value}")?,
+                Declaration::Alias(name, key) => writeln!(f, "alias {name} = {key}")?,
+                Declaration::Comment(text) => writeln!(f, "{text}")?,
+            }
+            separate = true;
+        }
+
+        if !self.hotstrings.is_empty() {
+            if separate {
+                writeln!(f)?;
+            }
+            for hotstring in &self.hotstrings {
+                writeln!(f, "{hotstring}")?;
+            }
+            separate = true;
+        }
+
+        for script_macro in &self.macros {
+            if separate {
+                writeln!(f)?;
+            }
+            write!(f, "{script_macro}")?;
+            separate = true;
+        }
+
+        for block in &self.blocks {
+            if separate {
+                writeln!(f)?;
+            }
+            write!(f, "{block}")?;
+            separate = true;
+        }
+
+        if separate && !self.end_comments.is_empty() {
+            writeln!(f)?;
+        }
+        write_comments(f, &self.end_comments)
+    }
+}
+
+/// Where a line is, for error messages. Lines from included files also name
+/// the file.
+#[derive(Clone, Copy)]
+struct LineNumber<'a> {
+    number: usize,
+    file: Option<&'a Path>,
+}
+
+impl fmt::Display for LineNumber<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.number)?;
+
+        if let Some(file) = self.file {
+            write!(f, " of {}", file.display())?;
         }
+
+        Ok(())
+    }
+}
+
+/// State shared by a script and the files it includes.
+struct ParseContext {
+    constant_values: HashMap<String, i64>,
+    aliases: HashMap<String, String>,
+    /// Variables set by `let` actions, and every variable read with the line
+    /// it was read on, so typos can be caught
+    assigned: HashSet<String>,
+    reads: Vec<(String, String)>,
+    /// Every macro call with its argument count and line, checked once every
+    /// macro has been parsed.
+    calls: Vec<(String, usize, String)>,
+    /// Where to look for included files that aren't next to the script.
+    include_dirs: Vec<PathBuf>,
+    /// The files currently being parsed, to catch includes that loop.
+    stack: Vec<PathBuf>,
+    included: HashSet<PathBuf>,
+}
+
+impl ParseContext {
+    fn new(include_dirs: &[PathBuf]) -> Self {
+        Self {
+            constant_values: HashMap::new(),
+            aliases: HashMap::new(),
+            assigned: HashSet::new(),
+            reads: Vec::new(),
+            calls: Vec::new(),
+            include_dirs: include_dirs.to_vec(),
+            stack: Vec::new(),
+            included: HashSet::new(),
+        }
+    }
+
+    fn read(&mut self, names: Vec<&str>, line_number: LineNumber) {
+        self.reads.extend(
+            names
+                .into_iter()
+                .map(|name| (name.to_string(), line_number.to_string())),
+        );
+    }
+
+    /// Finds an included file next to the including one, then in the
+    /// include directories.
+    fn resolve(&self, path: &str, base_dir: &Path) -> Option<PathBuf> {
+        std::iter::once(base_dir)
+            .chain(self.include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|path| path.is_file())
+    }
+}
+
+/// The actions of the innermost open `{`, or of the block currently being
+/// parsed. `release` picks the list run when the trigger is released.
+fn block_actions<'a>(
+    blocks: &'a mut [Block],
+    open: &'a mut [(Open, Vec<Action>)],
+    release: bool,
+    line_number: LineNumber,
+) -> &'a mut Vec<Action> {
+    if let Some((_, actions)) = open.last_mut() {
+        return actions;
+    }
+
+    let block = blocks
+        .last_mut()
+        .unwrap_or_else(|| panic!("No trigger set before line {line_number}"));
+
+    if release {
+        &mut block.release_actions
+    } else {
+        &mut block.actions
+    }
+}
+
+/// Parses the condition of an `if` or `while` line, up to and including the
+/// `{` that opens it.
+fn parse_condition<'a>(
+    mut words: impl Iterator<Item = &'a str>,
+    block: Option<&Block>,
+    aliases: &HashMap<String, String>,
+    line_number: LineNumber,
+) -> Condition {
+    let kind = words.next();
+    let input = words
+        .next()
+        .unwrap_or_else(|| panic!("Expected {{ after the condition. Line {line_number}"));
+
+    let (condition, opened) = match (kind, input.split_once(':')) {
+        (Some("held"), _) if input == "{" => {
+            // Macros are checked against every block that can reach them
+            // once the whole script is parsed
+            if block
+                .is_some_and(|block| !matches!(block.trigger, Trigger::Key(_) | Trigger::Mouse(_)))
+            {
+                panic!("Only key and mouse triggers can be held. Line {line_number}");
+            }
+
+            (Condition::TriggerHeld, true)
+        }
+        (Some("held"), Some(("key" | "k", key))) => (
+            Condition::KeyHeld(
+                parse_trigger_key(key, aliases)
+                    .unwrap_or_else(|| panic!("Could not parse action value. Line {line_number}")),
+            ),
+            false,
+        ),
+        (Some("held"), Some(("mouse" | "m", button))) => (
+            Condition::ButtonHeld(
+                parse_trigger_button_string(button)
+                    .unwrap_or_else(|| panic!("Could not parse action value. Line {line_number}")),
+            ),
+            false,
+        ),
+        (Some("toggled"), _) => {
+            let key = input.strip_prefix("key:").unwrap_or(input);
+            let key = parse_trigger_key(key, aliases)
+                .filter(|key| LOCK_KEYS.contains(&key.code))
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Only capslock, numlock and scrolllock can be toggled. Line {line_number}"
+                    )
+                });
+
+            (Condition::Toggled(key), false)
+        }
+        _ => panic!("Could not parse condition. Line {line_number}"),
+    };
+
+    if !opened && words.next() != Some("{") {
+        panic!("Expected {{ after the condition. Line {line_number}");
+    }
+
+    condition
+}
+
+/// Names of the macros that always get called when the actions run, calls
+/// inside an `if` or `while` may not happen.
+fn unconditional_calls(actions: &[Action]) -> Vec<&str> {
+    actions
+        .iter()
+        .flat_map(|action| match action {
+            Action::Call { name, .. } => vec![name.as_str()],
+            Action::Loop {
+                kind: LoopKind::Count(_) | LoopKind::Forever,
+                actions,
+            } => unconditional_calls(actions),
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Every call in the actions, including ones inside loops and conditions.
+fn all_calls(actions: &[Action]) -> Vec<(&str, usize)> {
+    actions
+        .iter()
+        .flat_map(|action| match action {
+            Action::Call { name, args } => vec![(name.as_str(), args.len())],
+            Action::Loop { actions, .. } => all_calls(actions),
+            Action::If {
+                actions,
+                else_actions,
+                ..
+            } => {
+                let mut calls = all_calls(actions);
+                calls.extend(all_calls(else_actions));
+                calls
+            }
+            _ => Vec::new(),
+        })
+        .collect()
+}
+
+/// Whether any of the actions, at any depth, check if the trigger is held.
+fn reads_trigger_held(actions: &[Action]) -> bool {
+    actions.iter().any(|action| match action {
+        Action::Loop { kind, actions } => {
+            matches!(kind, LoopKind::While(Condition::TriggerHeld)) || reads_trigger_held(actions)
+        }
+        Action::If {
+            condition,
+            actions,
+            else_actions,
+        } => {
+            *condition == Condition::TriggerHeld
+                || reads_trigger_held(actions)
+                || reads_trigger_held(else_actions)
+        }
+        _ => false,
+    })
+}
+
+/// Panics if a macro calls one that doesn't exist, with the wrong number of
+/// arguments, or always ends up calling itself, or if a trigger that can't be
+/// held reaches a macro that checks whether it's held.
+fn check_macros(macros: &[&Macro], blocks: &[&Block], calls: &[(String, usize, String)]) {
+    let find = |name: &str| macros.iter().find(|script_macro| script_macro.name == name);
+
+    for (index, script_macro) in macros.iter().enumerate() {
+        if macros[..index]
+            .iter()
+            .any(|other| other.name == script_macro.name)
+        {
+            panic!("Macro {} is defined more than once.", script_macro.name);
+        }
+    }
+
+    for (name, arg_count, line_number) in calls {
+        let script_macro =
+            find(name).unwrap_or_else(|| panic!("Unknown macro {name}. Line {line_number}"));
+
+        if script_macro.params.len() != *arg_count {
+            panic!(
+                "Macro {name} takes {} arguments, not {arg_count}. Line {line_number}",
+                script_macro.params.len()
+            );
+        }
+    }
+
+    // Follows the calls that always happen, a macro reached again is a loop
+    // that can never end
+    for script_macro in macros.iter() {
+        let mut stack = vec![script_macro.name.as_str()];
+        let mut seen = HashSet::new();
+
+        while let Some(name) = stack.pop() {
+            for called in find(name)
+                .map(|m| unconditional_calls(&m.actions))
+                .unwrap_or_default()
+            {
+                if called == script_macro.name {
+                    panic!("Macro {} always calls itself.", script_macro.name);
+                }
+                if seen.insert(called) {
+                    stack.push(called);
+                }
+            }
+        }
+    }
+
+    for block in blocks {
+        if matches!(block.trigger, Trigger::Key(_) | Trigger::Mouse(_)) {
+            continue;
+        }
+
+        let mut stack: Vec<&str> = all_calls(&block.actions)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        let mut seen: HashSet<&str> = stack.iter().copied().collect();
+
+        while let Some(name) = stack.pop() {
+            let script_macro = find(name).expect("Calls were checked above.");
+            if reads_trigger_held(&script_macro.actions) {
+                panic!(
+                    "Macro {name} checks if the trigger is held, but {} can't be held.",
+                    block.trigger
+                );
+            }
+
+            for (called, _) in all_calls(&script_macro.actions) {
+                if seen.insert(called) {
+                    stack.push(called);
+                }
+            }
+        }
+    }
+}
+
+/// Evdev codes of caps lock, num lock and scroll lock.
+pub const LOCK_KEYS: [u32; 3] = [58, 69, 70];
+
+impl Script {
+    /// Reads a script and the files it includes, `include_dirs` are searched
+    /// for included files that aren't next to the file including them.
+    pub fn read(script_path: String, include_dirs: &[PathBuf]) -> Self {
+        let file = std::fs::read_to_string(&script_path)
+            .unwrap_or_else(|_| panic!("File not found: {script_path}"));
+        let path = Path::new(&script_path);
+
+        let mut context = ParseContext::new(include_dirs);
+        context.stack.extend(path.canonicalize().ok());
+        context.included.extend(path.canonicalize().ok());
+
+        let script = Self::parse_file(
+            &file,
+            None,
+            path.parent().unwrap_or(Path::new(".")),
+            &mut context,
+        );
+        script.validate(&context);
+        script
+    }
+
+    /// Parses a script, included files are looked for in the current
+    /// directory.
+    #[cfg(test)]
+    pub fn parse(source: &str) -> Self {
+        let mut context = ParseContext::new(&[]);
+
+        let script = Self::parse_file(source, None, Path::new("."), &mut context);
+        script.validate(&context);
+        script
+    }
+
+    /// This script followed by every script it includes.
+    fn scripts(&self) -> Vec<&Script> {
+        let mut scripts = vec![self];
+
+        for declaration in &self.declarations {
+            if let Declaration::Include(Include {
+                script: Some(script),
+                ..
+            }) = declaration
+            {
+                scripts.extend(script.scripts());
+            }
+        }
+
+        scripts
+    }
+
+    /// Checks the things that can only be checked once every included file
+    /// has been parsed.
+    fn validate(&self, context: &ParseContext) {
+        if let Some((name, line_number)) = context
+            .reads
+            .iter()
+            .find(|(name, _)| !context.assigned.contains(name))
+        {
+            panic!("Unknown variable {name}. Line {line_number}");
+        }
+
+        let scripts = self.scripts();
+        let macros: Vec<&Macro> = scripts.iter().flat_map(|script| &script.macros).collect();
+        let blocks: Vec<&Block> = scripts.iter().flat_map(|script| &script.blocks).collect();
+        check_macros(&macros, &blocks, &context.calls);
+    }
+
+    /// Moves the contents of included files into this script, ahead of its
+    /// own. Declarations from included files take the place of the include.
+    pub fn flatten(self) -> Self {
+        let mut script = Script {
+            seat_comments: Vec::new(),
+            seat: None,
+            declarations: Vec::new(),
+            hotstrings: Vec::new(),
+            macros: Vec::new(),
+            blocks: Vec::new(),
+            end_comments: Vec::new(),
+        };
+
+        for declaration in self.declarations {
+            let Declaration::Include(include) = declaration else {
+                script.declarations.push(declaration);
+                continue;
+            };
+            let Some(included) = include.script else {
+                continue;
+            };
+            let included = included.flatten();
+
+            script.seat = script.seat.or(included.seat);
+            script.declarations.extend(included.declarations);
+            script.hotstrings.extend(included.hotstrings);
+            script.macros.extend(included.macros);
+            script.blocks.extend(included.blocks);
+        }
+
+        script.seat = self.seat.or(script.seat);
+        script.hotstrings.extend(self.hotstrings);
+        script.macros.extend(self.macros);
+        script.blocks.extend(self.blocks);
+        script
+    }
+
+    /// Parses the lines of one file, `file` is `None` for the script itself
+    /// and `base_dir` is where its includes are looked for first.
+    fn parse_file(
+        source: &str,
+        file: Option<&Path>,
+        base_dir: &Path,
+        context: &mut ParseContext,
+    ) -> Self {
+        let mut seat_comments: Vec<String> = Vec::new();
+        let mut seat: Option<String> = None;
+        // Comment lines that haven't been given to a line below them yet
+        let mut comments: Vec<String> = Vec::new();
+        let mut declarations: Vec<Declaration> = Vec::new();
+        let mut hotstrings: Vec<Hotstring> = Vec::new();
+        let mut blocks: Vec<Block> = Vec::new();
+        // Whether actions go to the release list
+        let mut release = false;
+        // Loops that have been opened but not closed yet, innermost last
+        let mut open: Vec<(Open, Vec<Action>)> = Vec::new();
+        let mut macros: Vec<Macro> = Vec::new();
+
+        for (index, raw_line) in source.lines().map(str::trim).enumerate() {
+            let line_number = LineNumber {
+                number: index + 1,
+                file,
+            };
+            let line = raw_line.to_lowercase();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('#') {
+                comments.push(raw_line.to_string());
+                continue;
+            }
+
+            let mut words = split_words(&line).into_iter();
+            let operation = words
+                .next()
+                .unwrap_or_else(|| panic!("No operation on line: {line_number}"));
+
+            if matches!(operation, "on" | "press:" | "release:") && !open.is_empty() {
+                panic!("Unclosed {{ before line {line_number}");
+            }
+
+            // Comments above an action, or above the `}` of a list of actions,
+            // belong to that list. Any others go with the next top level line
+            let is_action = match operation {
+                "event" | "press" | "hold" | "sleep" | "repeat" | "loop" | "while" | "if"
+                | "call" => true,
+                "let" => !blocks.is_empty() || !open.is_empty(),
+                "}" => !open.is_empty(),
+                _ => false,
+            };
+            if is_action && !comments.is_empty() {
+                block_actions(&mut blocks, &mut open, release, line_number)
+                    .extend(comments.drain(..).map(Action::Comment));
+            }
+
+            match operation {
+                "on" => {
+                    let (action_type, action_value) = words
+                        .next()
+                        .unwrap_or_else(|| panic!("No trigger passed. Line {line_number}"))
+                        .split_once(':')
+                        .unwrap_or_else(|| panic!("No action value passed. Line {line_number}"));
+
+                    let mut trigger = match action_type {
+                        "key" | "k" => {
+                            Trigger::Key(parse_trigger_key(action_value, &context.aliases).unwrap_or_else(
+                                || panic!("Could not parse action value. Line {line_number}"),
+                            ))
+                        }
+                        "mouse" | "m" => {
+                            Trigger::Mouse(parse_trigger_button_string(action_value).unwrap_or_else(
+                                || panic!("Could not parse action value. Line {line_number}"),
+                            ))
+                        }
+                        "device_added" => {
+                            Trigger::DeviceAdded(action_value.trim_matches('"').to_string())
+                        }
+                        "seq" | "s" => Trigger::Sequence {
+                            steps: action_value
+                                .split(',')
+                                .map(|step| {
+                                    step.split('+')
+                                        .map(|key| {
+                                            parse_trigger_key(key, &context.aliases).unwrap_or_else(|| {
+                                                panic!("Could not parse action value. Line {line_number}")
+                                            })
+                                        })
+                                        .collect()
+                                })
+                                .collect(),
+                            timeout: Duration::from_secs(1),
+                        },
+                        _ => panic!("Could not parse action type. Line {line_number}"),
+                    };
+
+                    let mut is_repeating = false;
+                    let mut device = None;
+                    let mut timing = None;
+                    let mut jitter = None;
+                    let mut click_time = None;
+                    let mut both = false;
+                    release = false;
+
+                    for word in words {
+                        match word.split_once('=') {
+                            Some(("device", pattern)) => {
+                                device = Some(pattern.trim_matches('"').to_string());
+                            }
+                            Some(("timeout", duration)) => match &mut trigger {
+                                Trigger::Sequence { timeout, .. } => {
+                                    *timeout = parse_duration(duration).unwrap_or_else(|| {
+                                        panic!("Could not parse duration. Line {line_number}")
+                                    });
+                                }
+                                _ => panic!(
+                                    "Only sequence triggers have a timeout. Line {line_number}"
+                                ),
+                            },
+                            Some((kind @ ("hold" | "double" | "tap"), duration)) => {
+                                let duration = parse_duration(duration).unwrap_or_else(|| {
+                                    panic!("Could not parse duration. Line {line_number}")
+                                });
+
+                                timing = Some(match kind {
+                                    "hold" => Timing::Hold(duration),
+                                    "double" => Timing::Double(duration),
+                                    _ => Timing::Tap(duration),
+                                });
+                            }
+                            Some(("jitter", duration)) => {
+                                // The spread is the same both ways, `±15ms` reads better
+                                let duration = duration.trim_start_matches(['±', '+', '-']);
+                                jitter = Some(parse_duration(duration).unwrap_or_else(|| {
+                                    panic!("Could not parse duration. Line {line_number}")
+                                }));
+                            }
+                            Some(("click_time", range)) => {
+                                click_time =
+                                    Some(parse_duration_range(range).unwrap_or_else(|| {
+                                        panic!("Could not parse duration range. Line {line_number}")
+                                    }));
+                            }
+                            _ => match word {
+                                "repeating" | "repeat" | "r" => is_repeating = true,
+                                "once" => is_repeating = false,
+                                "on_release" => release = true,
+                                // Does nothing over `press:` and `release:` sections,
+                                // but is still checked like `on_release`
+                                "both" => both = true,
+                                _ => panic!("Could not parse trigger modifier. Line {line_number}"),
+                            },
+                        }
+                    }
+
+                    if release && both {
+                        panic!("on_release and both can't be used together. Line {line_number}");
+                    }
+                    if matches!(trigger, Trigger::DeviceAdded(_) | Trigger::Sequence { .. })
+                        && (timing.is_some() || is_repeating || release || both)
+                    {
+                        panic!(
+                            "This trigger can't repeat, have timing or run on release. Line {line_number}"
+                        );
+                    }
+                    if (release || both) && (timing.is_some() || is_repeating) {
+                        panic!("Release triggers can't repeat or have timing. Line {line_number}");
+                    }
+                    if is_repeating && matches!(timing, Some(Timing::Tap(_))) {
+                        panic!("Tap triggers can't repeat. Line {line_number}");
+                    }
+
+                    blocks.push(Block {
+                        comments: std::mem::take(&mut comments),
+                        is_repeating,
+                        trigger,
+                        device,
+                        timing,
+                        jitter,
+                        click_time,
+                        actions: Vec::new(),
+                        release_actions: Vec::new(),
+                    });
+                }
+                section @ ("press:" | "release:") => {
+                    let block = blocks
+                        .last()
+                        .unwrap_or_else(|| panic!("No trigger set before line {line_number}"));
+
+                    release = section == "release:";
+                    if release
+                        && (matches!(block.timing, Some(Timing::Double(_) | Timing::Tap(_)))
+                            || !matches!(block.trigger, Trigger::Key(_) | Trigger::Mouse(_)))
+                    {
+                        panic!(
+                            "Only key and mouse triggers without double or tap have release actions. Line {line_number}"
+                        );
+                    }
+                }
+                "event" => {
+                    let input = parse_input(
+                        words
+                            .next()
+                            .unwrap_or_else(|| panic!("No event passed. Line {line_number}")),
+                        &context.aliases,
+                        line_number,
+                    );
+                    let action_direction = words.next();
+
+                    let direction = action_direction
+                        .map(|ad| {
+                            parse_direction(ad).unwrap_or_else(|| {
+                                panic!("Could not parse direction. Line {line_number}")
+                            })
+                        })
+                        .unwrap_or(Direction::Click);
+
+                    block_actions(&mut blocks, &mut open, release, line_number).push(match input {
+                        Input::Key(key) => Action::KeyEvent { key, direction },
+                        Input::Mouse(code) => Action::MouseEvent { code, direction },
+                    });
+                }
+                "press" => {
+                    let keys: Vec<ActionKey> = words
+                        .next()
+                        .unwrap_or_else(|| panic!("No keys passed. Line {line_number}"))
+                        .split('+')
+                        .map(|key| {
+                            parse_action_key(key, &context.aliases).unwrap_or_else(|| {
+                                panic!("Could not parse action value. Line {line_number}")
+                            })
+                        })
+                        .collect();
+
+                    let mut delay = None;
+                    for word in words {
+                        match word.split_once('=') {
+                            Some(("delay", duration)) => {
+                                let duration =
+                                    parse_duration_value(duration, &context.constant_values)
+                                        .unwrap_or_else(|| {
+                                            panic!("Could not parse duration. Line {line_number}")
+                                        });
+                                if let DurationValue::Expr(expr) = &duration {
+                                    context.read(expr.variables(), line_number);
+                                }
+                                delay = Some(duration);
+                            }
+                            _ => panic!("Could not parse press modifier. Line {line_number}"),
+                        }
+                    }
+
+                    block_actions(&mut blocks, &mut open, release, line_number)
+                        .push(Action::Press { keys, delay });
+                }
+                "hold" => {
+                    let input = parse_input(
+                        words.next().unwrap_or_else(|| {
+                            panic!("No key or button passed. Line {line_number}")
+                        }),
+                        &context.aliases,
+                        line_number,
+                    );
+                    let duration = words
+                        .next()
+                        .and_then(|duration| {
+                            parse_duration_value(duration, &context.constant_values)
+                        })
+                        .unwrap_or_else(|| panic!("Could not parse duration. Line {line_number}"));
+                    if let DurationValue::Expr(expr) = &duration {
+                        context.read(expr.variables(), line_number);
+                    }
+
+                    block_actions(&mut blocks, &mut open, release, line_number)
+                        .push(Action::Hold { input, duration });
+                }
+                "hotstring" => {
+                    // Read from the original line so the replacement keeps its case
+                    let mut words = split_words(raw_line).into_iter().skip(1);
+                    let mut next_string = || {
+                        words
+                            .next()
+                            .map(|word| word.trim_matches('"').to_string())
+                            .unwrap_or_else(|| panic!("Incomplete hotstring. Line {line_number}"))
+                    };
+
+                    let abbreviation = next_string().to_lowercase();
+                    if next_string() != "->" {
+                        panic!("Expected -> after the abbreviation. Line {line_number}");
+                    }
+                    let replacement = Text::parse(&next_string(), &context.constant_values)
+                        .unwrap_or_else(|| {
+                            panic!("Could not parse replacement. Line {line_number}")
+                        });
+                    context.read(replacement.variables(), line_number);
+
+                    if abbreviation.is_empty()
+                        || abbreviation
+                            .chars()
+                            .any(|c| c.is_whitespace() || Hotstring::is_end_char(c))
+                    {
+                        panic!(
+                            "Abbreviations can't be empty or contain spaces or punctuation. Line {line_number}"
+                        );
+                    }
+
+                    hotstrings.push(Hotstring {
+                        comments: std::mem::take(&mut comments),
+                        abbreviation,
+                        replacement,
+                    });
+                }
+                "seat" => {
+                    // Read from the original line as seat names are case sensitive
+                    let name = raw_line
+                        .split_whitespace()
+                        .nth(1)
+                        .unwrap_or_else(|| panic!("No seat passed. Line {line_number}"));
+
+                    seat_comments = std::mem::take(&mut comments);
+                    seat = Some(name.to_string());
+                }
+                "sleep" => {
+                    let duration_string = words
+                        .next()
+                        .unwrap_or_else(|| panic!("No duration passed. Line {line_number}"));
+                    let (duration_string, max_string) = split_range(duration_string);
+                    let parse = |value_string| {
+                        parse_duration_value(value_string, &context.constant_values).unwrap_or_else(
+                            || panic!("Could not parse duration. Line {line_number}"),
+                        )
+                    };
+                    let duration = parse(&duration_string);
+                    let max = max_string.map(parse);
+                    for value in [Some(&duration), max.as_ref()].into_iter().flatten() {
+                        if let DurationValue::Expr(expr) = value {
+                            context.read(expr.variables(), line_number);
+                        }
+                    }
+
+                    block_actions(&mut blocks, &mut open, release, line_number)
+                        .push(Action::Sleep { duration, max });
+                }
+                "repeat" | "loop" | "while" | "if" => {
+                    let block =
+                        match open.first() {
+                            Some((Open::Macro { .. }, _)) => None,
+                            _ => Some(blocks.last().unwrap_or_else(|| {
+                                panic!("No trigger set before line {line_number}")
+                            })),
+                        };
+
+                    let kind = match operation {
+                        "repeat" => {
+                            let count = words
+                                .next()
+                                .and_then(|count| parse_value(count, &context.constant_values))
+                                .unwrap_or_else(|| {
+                                    panic!("Could not parse repeat count. Line {line_number}")
+                                });
+                            context.read(count.variables(), line_number);
+
+                            LoopKind::Count(count)
+                        }
+                        "loop" => LoopKind::Forever,
+                        "while" => LoopKind::While(parse_condition(
+                            &mut words,
+                            block,
+                            &context.aliases,
+                            line_number,
+                        )),
+                        _ => {
+                            let condition =
+                                parse_condition(&mut words, block, &context.aliases, line_number);
+                            open.push((Open::If(condition), Vec::new()));
+                            continue;
+                        }
+                    };
+
+                    if matches!(kind, LoopKind::Count(_) | LoopKind::Forever)
+                        && words.next() != Some("{")
+                    {
+                        panic!("Expected {{ after the loop. Line {line_number}");
+                    }
+
+                    open.push((Open::Loop(kind), Vec::new()));
+                }
+                "include" => {
+                    if !open.is_empty() {
+                        panic!("Includes can't be inside a {{. Line {line_number}");
+                    }
+
+                    // Read from the original line as paths are case sensitive
+                    let include_path = raw_line["include".len()..].trim().trim_matches('"');
+                    let resolved = context.resolve(include_path, base_dir).unwrap_or_else(|| {
+                        panic!("Could not find {include_path}. Line {line_number}")
+                    });
+                    let canonical = resolved.canonicalize().unwrap_or_else(|_| resolved.clone());
+
+                    if context.stack.contains(&canonical) {
+                        panic!("{include_path} ends up including itself. Line {line_number}");
+                    }
+
+                    // Files included more than once are only parsed the first time
+                    let script = context.included.insert(canonical.clone()).then(|| {
+                        let source = std::fs::read_to_string(&resolved).unwrap_or_else(|_| {
+                            panic!("Could not read {include_path}. Line {line_number}")
+                        });
+
+                        context.stack.push(canonical);
+                        let script = Self::parse_file(
+                            &source,
+                            Some(&resolved),
+                            resolved.parent().unwrap_or(Path::new(".")),
+                            context,
+                        );
+                        context.stack.pop();
+
+                        script
+                    });
+
+                    declarations.extend(comments.drain(..).map(Declaration::Comment));
+                    declarations.push(Declaration::Include(Include {
+                        path: include_path.to_string(),
+                        script,
+                    }));
+                }
+                "macro" => {
+                    if !open.is_empty() {
+                        panic!("Macros can't be inside another {{. Line {line_number}");
+                    }
+
+                    let mut names: Vec<String> = words.map(str::to_string).collect();
+                    if names.pop().as_deref() != Some("{") || names.is_empty() {
+                        panic!("Expected a name and {{ after macro. Line {line_number}");
+                    }
+                    let params = names.split_off(1);
+                    let name = names.remove(0);
+
+                    if macros.iter().any(|script_macro| script_macro.name == name) {
+                        panic!("Macro {name} is already defined. Line {line_number}");
+                    }
+                    if let Some(param) = params
+                        .iter()
+                        .find(|param| context.constant_values.contains_key(*param))
+                    {
+                        panic!(
+                            "{param} is a constant and can't be a parameter. Line {line_number}"
+                        );
+                    }
+
+                    open.push((
+                        Open::Macro {
+                            comments: std::mem::take(&mut comments),
+                            name,
+                            params,
+                            reads_start: context.reads.len(),
+                        },
+                        Vec::new(),
+                    ));
+                }
+                "call" => {
+                    let name = words
+                        .next()
+                        .unwrap_or_else(|| panic!("No macro passed. Line {line_number}"))
+                        .to_string();
+                    let args: Vec<Expr> = words
+                        .map(|arg| {
+                            parse_value(arg, &context.constant_values).unwrap_or_else(|| {
+                                panic!("Could not parse argument. Line {line_number}")
+                            })
+                        })
+                        .collect();
+                    context.read(args.iter().flat_map(Expr::variables).collect(), line_number);
+                    context
+                        .calls
+                        .push((name.clone(), args.len(), line_number.to_string()));
+
+                    block_actions(&mut blocks, &mut open, release, line_number)
+                        .push(Action::Call { name, args });
+                }
+                "alias" => {
+                    if !blocks.is_empty() || !open.is_empty() {
+                        panic!("Aliases must come before the first hotkey. Line {line_number}");
+                    }
+
+                    let (name, key) = line["alias".len()..]
+                        .split_once('=')
+                        .unwrap_or_else(|| panic!("Expected = after the name. Line {line_number}"));
+                    let (name, key) = (name.trim(), key.trim());
+
+                    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                        panic!("Could not parse alias name. Line {line_number}");
+                    }
+                    if parse_trigger_key_string(name).is_some()
+                        || parse_action_key_string(name).is_some()
+                    {
+                        panic!("{name} is already a key name. Line {line_number}");
+                    }
+                    if context.aliases.contains_key(name) {
+                        panic!("Alias {name} is defined more than once. Line {line_number}");
+                    }
+
+                    // Aliases of aliases are looked up as the key they end up at,
+                    // but written back out as they were given
+                    let (resolved, _) = resolve_alias(key, &context.aliases);
+                    if parse_trigger_key_string(resolved).is_none()
+                        && parse_action_key_string(resolved).is_none()
+                    {
+                        panic!("Could not parse key. Line {line_number}");
+                    }
+
+                    context
+                        .aliases
+                        .insert(name.to_string(), resolved.to_string());
+                    declarations.extend(comments.drain(..).map(Declaration::Comment));
+                    declarations.push(Declaration::Alias(name.to_string(), key.to_string()));
+                }
+                "let" => {
+                    let (name, value) = line["let".len()..]
+                        .split_once('=')
+                        .unwrap_or_else(|| panic!("Expected = after the name. Line {line_number}"));
+                    let name = name.trim();
+
+                    if name.is_empty()
+                        || name.starts_with(|c: char| c.is_ascii_digit())
+                        || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+                    {
+                        panic!("Could not parse variable name. Line {line_number}");
+                    }
+
+                    let value = Expr::parse(value, &context.constant_values).unwrap_or_else(|| {
+                        panic!("Could not parse expression. Line {line_number}")
+                    });
+
+                    if blocks.is_empty() && open.is_empty() {
+                        let constant = value.constant().unwrap_or_else(|| {
+                            panic!(
+                                "Constants can only use numbers and earlier constants. Line {line_number}"
+                            )
+                        });
+
+                        context.constant_values.insert(name.to_string(), constant);
+                        declarations.extend(comments.drain(..).map(Declaration::Comment));
+                        declarations.push(Declaration::Constant(name.to_string(), value));
+                    } else {
+                        if context.constant_values.contains_key(name) {
+                            panic!("{name} is a constant and can't be changed. Line {line_number}");
+                        }
+
+                        context.read(value.variables(), line_number);
+                        context.assigned.insert(name.to_string());
+
+                        block_actions(&mut blocks, &mut open, release, line_number).push(
+                            Action::Let {
+                                name: name.to_string(),
+                                value,
+                            },
+                        );
+                    }
+                }
+                "}" => {
+                    let (opened, actions) = open
+                        .pop()
+                        .unwrap_or_else(|| panic!("Nothing to close. Line {line_number}"));
+
+                    let action = match (opened, words.next()) {
+                        (Open::If(condition), Some("else")) => {
+                            if words.next() != Some("{") {
+                                panic!("Expected {{ after else. Line {line_number}");
+                            }
+
+                            open.push((Open::Else(condition, actions), Vec::new()));
+                            continue;
+                        }
+                        (_, Some(_)) => panic!("Could not parse }}. Line {line_number}"),
+                        (
+                            Open::Macro {
+                                comments,
+                                name,
+                                params,
+                                reads_start,
+                            },
+                            None,
+                        ) => {
+                            // Parameters are only known inside the macro
+                            let body = context.reads.split_off(reads_start);
+                            context.reads.extend(
+                                body.into_iter().filter(|(read, _)| !params.contains(read)),
+                            );
+
+                            macros.push(Macro {
+                                comments,
+                                name,
+                                params,
+                                actions,
+                            });
+                            continue;
+                        }
+                        (Open::Loop(kind), None) => Action::Loop { kind, actions },
+                        (Open::If(condition), None) => Action::If {
+                            condition,
+                            actions,
+                            else_actions: Vec::new(),
+                        },
+                        (Open::Else(condition, then_actions), None) => Action::If {
+                            condition,
+                            actions: then_actions,
+                            else_actions: actions,
+                        },
+                    };
+
+                    block_actions(&mut blocks, &mut open, release, line_number).push(action);
+                }
+                _ => panic!("Could not parse operation. Line {line_number}"),
+            }
+        }
+
+        if !open.is_empty() {
+            match file {
+                Some(file) => panic!("Unclosed {{ at the end of {}.", file.display()),
+                None => panic!("Unclosed {{ at the end of the script."),
+            }
+        }
+
+        Self {
+            seat_comments,
+            seat,
+            declarations,
+            hotstrings,
+            macros,
+            blocks,
+            end_comments: comments,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Small xorshift generator so the round-trip test covers many scripts
+    /// without pulling in a property testing crate.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn coin(&mut self) -> bool {
+            self.next() & 1 == 0
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[(self.next() % items.len() as u64) as usize]
+        }
+    }
+
+    fn random_key(rng: &mut Rng) -> ActionKey {
+        let key = match rng.next() % 4 {
+            0 => EventKey::Key(Key::Unicode(rng.pick(&['a', '7', '~', '@', 'é', '\\']))),
+            1 => EventKey::Raw((rng.next() % 600) as u16),
+            2 => EventKey::Key(Key::Other(rng.next() as u32)),
+            _ => keys::KEYS[rng.next() as usize % keys::KEYS.len()]
+                .event_key()
+                .unwrap(),
+        };
+
+        ActionKey { key, alias: None }
+    }
+
+    fn random_trigger_key(rng: &mut Rng) -> TriggerKey {
+        if rng.coin() {
+            return TriggerKey::new((rng.next() % 300) as u32);
+        }
+
+        let (_, left, right) = rng.pick(keys::EITHER_SIDE);
+        TriggerKey {
+            code: left.into(),
+            right: Some(right.into()),
+            alias: None,
+        }
+    }
+
+    fn random_duration(rng: &mut Rng) -> Duration {
+        let unit = rng.pick(&[1, 1_000, 1_000_000, 60_000_000]);
+        // Short enough that the sum of two stays under `MAX_DURATION`
+        Duration::from_micros(rng.next() % 700 * unit)
+    }
+
+    fn random_comments(rng: &mut Rng) -> Vec<String> {
+        (0..rng.next() % 3)
+            .map(|_| rng.pick(&["# Jump", "#twice, In Case {", "#"]).to_string())
+            .collect()
+    }
+
+    /// Comments at the end of a hotkey go with whatever comes after it, so
+    /// they can only be generated inside `{` lists.
+    fn random_block_actions(rng: &mut Rng, can_hold: bool) -> Vec<Action> {
+        let mut actions = random_actions(rng, 0, can_hold);
+        while matches!(actions.last(), Some(Action::Comment(_))) {
+            actions.pop();
+        }
+        actions
+    }
+
+    fn random_block(rng: &mut Rng) -> Block {
+        let trigger = match rng.next() % 4 {
+            0 => Trigger::Key(TriggerKey::new((rng.next() % 600) as u32)),
+            1 => Trigger::Mouse(0x100 + (rng.next() % 0x30) as u32),
+            2 => Trigger::DeviceAdded(rng.pick(&["usb keyboard", "1532:*"]).to_string()),
+            _ => Trigger::Sequence {
+                steps: (0..1 + rng.next() % 3)
+                    .map(|_| {
+                        (0..1 + rng.next() % 2)
+                            .map(|_| random_trigger_key(rng))
+                            .collect()
+                    })
+                    .collect(),
+                timeout: random_duration(rng),
+            },
+        };
+        let is_simple = matches!(trigger, Trigger::Key(_) | Trigger::Mouse(_));
+        let timing = match (is_simple, rng.next() % 4) {
+            (false, _) | (_, 0) => None,
+            (_, 1) => Some(Timing::Hold(random_duration(rng))),
+            (_, 2) => Some(Timing::Double(random_duration(rng))),
+            _ => Some(Timing::Tap(random_duration(rng))),
+        };
+
+        let is_repeating = is_simple && !matches!(timing, Some(Timing::Tap(_))) && rng.coin();
+        let on_release = is_simple && matches!(timing, None | Some(Timing::Hold(_))) && rng.coin();
+
+        Block {
+            comments: random_comments(rng),
+            is_repeating,
+            trigger,
+            device: rng
+                .pick(&[None, Some("razer naga*"), Some("event5"), Some("046d:c52b")])
+                .map(str::to_string),
+            timing,
+            jitter: rng.coin().then(|| random_duration(rng)),
+            click_time: rng.coin().then(|| {
+                let min = random_duration(rng);
+                let max = if rng.coin() {
+                    min
+                } else {
+                    min + random_duration(rng)
+                };
+                (min, max)
+            }),
+            actions: random_block_actions(rng, is_simple),
+            release_actions: if on_release {
+                random_block_actions(rng, is_simple)
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    fn random_actions(rng: &mut Rng, depth: usize, can_hold: bool) -> Vec<Action> {
+        let directions = [Direction::Press, Direction::Release, Direction::Click];
+
+        (0..rng.next() % 12)
+            .map(|_| match rng.next() % 4 {
+                0 if rng.coin() && rng.coin() => {
+                    Action::Comment(rng.pick(&["# Jump", "#twice"]).to_string())
+                }
+                0 => Action::KeyEvent {
+                    key: random_key(rng),
+                    direction: rng.pick(&directions),
+                },
+                1 => Action::MouseEvent {
+                    code: (rng.next() % 6) as u16,
+                    direction: rng.pick(&directions),
+                },
+                2 if rng.coin() => Action::Press {
+                    keys: (0..1 + rng.next() % 3).map(|_| random_key(rng)).collect(),
+                    delay: rng
+                        .coin()
+                        .then(|| DurationValue::Fixed(random_duration(rng))),
+                },
+                2 if rng.coin() => Action::Hold {
+                    input: if rng.coin() {
+                        Input::Key(random_key(rng))
+                    } else {
+                        Input::Mouse((rng.next() % 6) as u16)
+                    },
+                    duration: DurationValue::Fixed(random_duration(rng)),
+                },
+                3 if depth < 2 && rng.coin() => Action::Loop {
+                    kind: match rng.next() % 3 {
+                        0 if can_hold => LoopKind::While(Condition::TriggerHeld),
+                        1 => LoopKind::Forever,
+                        _ => LoopKind::Count(Expr::Number((rng.next() % 100) as i64)),
+                    },
+                    actions: random_actions(rng, depth + 1, can_hold),
+                },
+                3 if depth < 2 => Action::If {
+                    condition: match rng.next() % 3 {
+                        0 => Condition::KeyHeld(random_trigger_key(rng)),
+                        1 => Condition::ButtonHeld(0x100 + (rng.next() % 0x30) as u32),
+                        _ => Condition::Toggled(TriggerKey::new(rng.pick(&LOCK_KEYS))),
+                    },
+                    actions: random_actions(rng, depth + 1, can_hold),
+                    else_actions: random_actions(rng, depth + 1, can_hold),
+                },
+                _ => Action::Sleep {
+                    duration: DurationValue::Fixed(random_duration(rng)),
+                    max: rng
+                        .coin()
+                        .then(|| DurationValue::Fixed(random_duration(rng))),
+                },
+            })
+            .collect()
+    }
+
+    fn random_script(rng: &mut Rng) -> Script {
+        let seat = rng
+            .pick(&[None, Some("seat0"), Some("Kiosk")])
+            .map(str::to_string);
+
+        Script {
+            seat_comments: if seat.is_some() {
+                random_comments(rng)
+            } else {
+                Vec::new()
+            },
+            seat,
+            // Hotstrings may read the delay constant, so it's always there
+            declarations: random_comments(rng)
+                .into_iter()
+                .map(Declaration::Comment)
+                .chain([Declaration::Constant("delay".to_string(), Expr::Number(10))])
+                .chain(
+                    rng.coin()
+                        .then(|| {
+                            [
+                                Declaration::Comment("# Launcher".to_string()),
+                                Declaration::Alias("launcher".to_string(), "f13".to_string()),
+                            ]
+                        })
+                        .into_iter()
+                        .flatten(),
+                )
+                .collect(),
+            hotstrings: (0..rng.next() % 3)
+                .map(|_| Hotstring {
+                    comments: random_comments(rng),
+                    abbreviation: rng.pick(&["btw", "omw", "@@"]).to_string(),
+                    replacement: Text::parse(
+                        rng.pick(&["by the way", "On my way!", "${delay + 1}ms"]),
+                        &HashMap::from([("delay".to_string(), 10)]),
+                    )
+                    .unwrap(),
+                })
+                .collect(),
+            macros: (0..rng.next() % 2)
+                .map(|_| Macro {
+                    comments: random_comments(rng),
+                    name: "reload".to_string(),
+                    params: vec!["wait".to_string()],
+                    actions: vec![
+                        Action::Sleep {
+                            duration: DurationValue::Expr(Expr::Variable("wait".to_string())),
+                            max: None,
+                        },
+                        Action::Comment("# Done".to_string()),
+                    ],
+                })
+                .collect(),
+            blocks: (0..1 + rng.next() % 3).map(|_| random_block(rng)).collect(),
+            end_comments: random_comments(rng),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng(0x5eed);
+
+        for _ in 0..1000 {
+            let script = random_script(&mut rng);
+            let text = script.to_string();
+
+            assert_eq!(Script::parse(&text), script, "{text}");
+            assert_eq!(Script::parse(&text).to_string(), text);
+        }
+    }
+
+    /// Asserts a script is written out in canonical form, and that the
+    /// canonical form reads back as itself.
+    fn assert_formats(source: &str, expected: &str) {
+        assert_eq!(Script::parse(source).to_string(), expected);
+        assert_eq!(Script::parse(expected).to_string(), expected);
+    }
+
+    #[test]
+    fn events() {
+        assert_formats(
+            "# comment\nON k:Lctrl r\nsleep 10\nevent key:ENTER d\nevent m:2\nevent key:Q up\n",
+            "# comment\non key:leftcontrol repeat\n\nsleep 10ms\nevent key:return press\nevent mouse:2 click\nevent key:q release\n",
+        );
+    }
+
+    #[test]
+    fn press_and_hold() {
+        assert_formats(
+            "on key:a\npress Ctrl+Shift+T\npress alt+f4 delay=20",
+            "on key:a once\n\npress leftcontrol+leftshift+t\npress leftalt+f4 delay=20ms\n",
+        );
+        assert_formats(
+            "on key:a\nhold key:w 2s\nhold m:1 ${100 * 5}",
+            "on key:a once\n\nhold key:w 2s\nhold mouse:1 ${100 * 5}\n",
+        );
+    }
+
+    #[test]
+    fn durations() {
+        assert_formats(
+            "on key:a\nsleep 1..2m\nsleep 0.25s\nsleep 1500us\nsleep 60000",
+            "on key:a once\n\nsleep 1m..2m\nsleep 250ms\nsleep 1500us\nsleep 1m\n",
+        );
+        assert_formats(
+            "on key:a jitter=±15ms click_time=20..40\nsleep 100..${2 * 70}",
+            "on key:a once jitter=15ms click_time=20ms..40ms\n\nsleep 100ms..${2 * 70}\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse duration. Line 2")]
+    fn duration_out_of_range() {
+        Script::parse("on key:a\nsleep 99999999999999999999m\n");
+    }
+
+    #[test]
+    fn trigger_modifiers() {
+        assert_formats(
+            "on key:f13 device=\"Razer Naga*\"  r",
+            "on key:f13 repeat device=\"razer naga*\"\n",
+        );
+        assert_formats(
+            "on key:caps tap=200\non key:caps hold=200ms r",
+            "on key:capslock once tap=200ms\n\non key:capslock repeat hold=200ms\n",
+        );
+    }
+
+    #[test]
+    fn mouse_triggers() {
+        assert_formats(
+            "on mouse:1\non m:#0x113\non mouse:EXTRA\non mouse:#280",
+            "on mouse:left once\n\non mouse:side once\n\non mouse:extra once\n\non mouse:#0x118 once\n",
+        );
+    }
+
+    #[test]
+    fn sequences() {
+        assert_formats(
+            "on seq:Ctrl+Space,g,#183",
+            "on seq:control+space,g,f13 once timeout=1s\n",
+        );
+        assert_formats("on seq:a timeout=1.5s", "on seq:a once timeout=1500ms\n");
+    }
+
+    #[test]
+    fn release_sections() {
+        assert_formats(
+            "on key:f1 on_release\nevent key:a\non key:f2 both\npress:\nsleep 5\nrelease:\nsleep 6",
+            "on key:f1 once on_release\n\nevent key:a click\n\non key:f2 once\n\npress:\nsleep 5ms\nrelease:\nsleep 6ms\n",
+        );
+        assert_formats(
+            "on key:shift r\nrelease:\nevent key:w up",
+            "on key:shift repeat\n\npress:\nrelease:\nevent key:w release\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Release triggers can't repeat or have timing. Line 1")]
+    fn both_with_repeat() {
+        Script::parse("on key:a both repeat hold=10\npress:\nrelease:\n");
+    }
+
+    #[test]
+    fn loops() {
+        assert_formats(
+            "on key:a\nrepeat 3 {\nloop {\nsleep 1\n}\n}\nwhile held {\n}",
+            "on key:a once\n\nrepeat 3 {\n    loop {\n        sleep 1ms\n    }\n}\nwhile held {\n}\n",
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        assert_formats(
+            "on key:a\nif held key:Shift {\nevent key:a\n} else {\nif toggled CAPSLOCK {\n}\n}\nwhile held mouse:left {\n}",
+            "on key:a once\n\nif held key:shift {\n    event key:a click\n} else {\n    if toggled capslock {\n    }\n}\nwhile held mouse:left {\n}\n",
+        );
+    }
+
+    #[test]
+    fn either_side_modifiers() {
+        let script = Script::parse("on key:ctrl\nif held key:shift {\n}\nif held key:lshift {\n}");
+        let Trigger::Key(trigger) = &script.blocks[0].trigger else {
+            unreachable!()
+        };
+        assert!(trigger.matches(29) && trigger.matches(97));
+
+        let held: Vec<&TriggerKey> = script.blocks[0]
+            .actions
+            .iter()
+            .map(|action| match action {
+                Action::If {
+                    condition: Condition::KeyHeld(key),
+                    ..
+                } => key,
+                _ => unreachable!(),
+            })
+            .collect();
+        assert!(held[0].matches(42) && held[0].matches(54));
+        assert!(held[1].matches(42) && !held[1].matches(54));
+    }
+
+    #[test]
+    fn variables() {
+        assert_formats(
+            "let Delay = 125\nlet long = delay * (2 + 1)\non key:a\nlet n = n + 1\nsleep ${delay}\nrepeat ${N % 3} {\nsleep ${-delay  * 2}\n}",
+            "let delay = 125\nlet long = delay * (2 + 1)\n\non key:a once\n\nlet n = n + 1\nsleep ${delay}\nrepeat ${n % 3} {\n    sleep ${(0 - delay) * 2}\n}\n",
+        );
+    }
+
+    #[test]
+    fn hotstrings() {
+        assert_formats(
+            "on key:a\nHOTSTRING \"BTW\" -> \"By The Way\"",
+            "hotstring \"btw\" -> \"By The Way\"\n\non key:a once\n",
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_formats(
+            "# Games\nseat Seat-Kiosk\n# Jump\non key:a\n  # Twice\nevent key:space\n  repeat 2 {\n  event key:b\n  # Done\n  }\n# The End\n",
+            "# Games\nseat Seat-Kiosk\n\n# Jump\non key:a once\n\n# Twice\nevent key:space click\nrepeat 2 {\n    event key:b click\n    # Done\n}\n\n# The End\n",
+        );
+    }
+
+    #[test]
+    fn seat_case() {
+        assert_eq!(
+            Script::parse("SEAT Seat-Kiosk\non key:a\n").seat.as_deref(),
+            Some("Seat-Kiosk")
+        );
+    }
+
+    #[test]
+    fn raw_keys() {
+        let script = Script::parse("on key:0xff1b\nevent key:#183 d\nevent key:0x1008FF13\n");
+
+        assert_eq!(script.blocks[0].trigger, Trigger::Key(TriggerKey::new(1)));
+        assert_eq!(
+            script.to_string(),
+            "on key:escape once\n\nevent key:#183 press\nevent key:0x1008ff13 click\n"
+        );
+        assert_eq!(
+            Script::parse("on key:#600\nevent key:#30").to_string(),
+            "on key:#600 once\n\nevent key:#30 click\n"
+        );
+        // Keys missing from the table fall back to their keysym, the only ones
+        // today are deprecated names
+        #[allow(deprecated)]
+        let key = EventKey::Key(Key::Super);
+        assert_eq!(key.to_string(), "0xffeb");
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse action value. Line 2")]
+    fn raw_key_out_of_range() {
+        Script::parse("on key:a\nevent key:#65535");
+    }
+
+    #[test]
+    #[should_panic(expected = "Could not parse action value. Line 1")]
+    fn raw_trigger_out_of_range() {
+        Script::parse("on key:#65535\nevent key:a");
+    }
+
+    #[test]
+    fn macros() {
+        assert_formats(
+            "on key:f5\ncall reload 2\nmacro Reload times {\nrepeat ${times} {\nevent key:f5\n}\n}\nmacro idle {\n}",
+            "macro reload times {\n    repeat ${times} {\n        event key:f5 click\n    }\n}\n\nmacro idle {\n}\n\non key:f5 once\n\ncall reload 2\n",
+        );
+    }
+
+    #[test]
+    fn held_in_macros() {
+        // Fine from a key, which can be held
+        Script::parse(
+            "on key:a\ncall outer\nmacro outer {\ncall inner\n}\nmacro inner {\nwhile held {\nsleep 10\n}\n}",
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Macro inner checks if the trigger is held, but seq:a,b can't be held."
+    )]
+    fn held_in_macros_called_from_sequence() {
+        Script::parse(
+            "on seq:a,b\ncall outer\nmacro outer {\nif held key:c {\ncall inner\n}\n}\nmacro inner {\nif held {\n}\n}",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown macro missing. Line 3")]
+    fn unknown_macro() {
+        Script::parse("on key:a\nsleep 10\ncall missing");
+    }
+
+    #[test]
+    #[should_panic(expected = "Macro tap takes 1 arguments, not 2. Line 2")]
+    fn macro_argument_count() {
+        Script::parse("on key:a\ncall tap 1 2\nmacro tap times {\n}");
+    }
+
+    #[test]
+    fn library_only() {
+        Script::parse("let tap = 10\nmacro tap_key {\nsleep ${tap}\n}");
+    }
+
+    #[test]
+    fn aliases() {
+        assert_formats(
+            "alias launcher = F13\nalias switcher = launcher\non key:switcher\npress ctrl+launcher\nhold key:switcher 10\nwhile held key:launcher {\n}",
+            "alias launcher = f13\nalias switcher = launcher\n\non key:switcher once\n\npress leftcontrol+launcher\nhold key:switcher 10ms\nwhile held key:launcher {\n}\n",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "tab is already a key name. Line 1")]
+    fn alias_of_key_name() {
+        Script::parse("alias tab = f13\non key:a");
+    }
+
+    #[test]
+    fn includes() {
+        let dir = std::env::temp_dir().join(format!("shk-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(
+            dir.join("lib/common.shk"),
+            "let tap = delay * 2\nalias launcher = f13\nmacro tap_key {\nsleep ${delay}\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("main.shk"),
+            "let delay = 10\ninclude \"common.shk\"\ninclude \"common.shk\"\non key:launcher\ncall tap_key\nsleep ${tap}",
+        )
+        .unwrap();
+
+        let path = dir.join("main.shk").to_string_lossy().to_string();
+        let text = Script::read(path.clone(), &[dir.join("lib")]).to_string();
+        assert_eq!(
+            text,
+            "let delay = 10\n\ninclude \"common.shk\"\ninclude \"common.shk\"\n\non key:launcher once\n\ncall tap_key\nsleep ${tap}\n"
+        );
+
+        // The formatted script still reads, with the constant ahead of the include
+        std::fs::write(dir.join("main.shk"), &text).unwrap();
+        let script = Script::read(path, &[dir.join("lib")]);
+        assert_eq!(script.to_string(), text);
+
+        let script = script.flatten();
+        let names: Vec<&str> = script
+            .declarations
+            .iter()
+            .map(|declaration| match declaration {
+                Declaration::Constant(name, _) | Declaration::Alias(name, _) => name.as_str(),
+                Declaration::Include(include) => &include.path,
+                Declaration::Comment(text) => text,
+            })
+            .collect();
+        assert_eq!(names, ["delay", "tap", "launcher"]);
+        assert_eq!(script.macros[0].name, "tap_key");
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
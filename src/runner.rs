@@ -1,34 +1,45 @@
 use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::atomic::{AtomicBool, Ordering},
     thread::sleep,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::parser::{Action, Script, Trigger};
+use crate::expr::Expr;
+use crate::keys::{self, EventKey};
+use crate::parser::{
+    Action, Block, Condition, DurationValue, Hotstring, Input, LOCK_KEYS, LoopKind, MAX_DURATION,
+    Script, Timing, Trigger, TriggerKey,
+};
 
 extern crate libc;
-use enigo::{Button, Enigo, Keyboard, Mouse, Settings};
+use enigo::{Button, Direction, Enigo, Key, Keyboard, Mouse, Settings};
 use input::{
-    Event, Libinput, LibinputInterface,
+    Device, DeviceCapability, Event, Libinput, LibinputInterface,
     event::{
-        PointerEvent,
-        keyboard::{KeyState, KeyboardEventTrait},
-        pointer::ButtonState,
+        DeviceEvent, EventTrait, PointerEvent,
+        keyboard::{KeyState, KeyboardEvent, KeyboardEventTrait},
+        pointer::{Axis, ButtonState, PointerEventTrait, PointerScrollEvent},
     },
 };
 use std::fs::{File, OpenOptions};
 use std::os::unix::{fs::OpenOptionsExt, io::OwnedFd};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use libc::{O_RDONLY, O_RDWR, O_WRONLY};
+use libc::{O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
+use xkbcommon::xkb;
 
 struct Interface;
 
 impl LibinputInterface for Interface {
     fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<OwnedFd, i32> {
+        let access = flags & O_ACCMODE;
+
         OpenOptions::new()
             .custom_flags(flags)
-            .read((flags & O_RDONLY != 0) | (flags & O_RDWR != 0))
-            .write((flags & O_WRONLY != 0) | (flags & O_RDWR != 0))
+            .read(access == O_RDONLY || access == O_RDWR)
+            .write(access == O_WRONLY || access == O_RDWR)
             .open(path)
             .map(|file| file.into())
             .map_err(|err| err.raw_os_error().unwrap_or(-1))
@@ -39,23 +50,513 @@ impl LibinputInterface for Interface {
     }
 }
 
-fn is_trigger(trigger: &Trigger, event: &Event) -> Option<bool> {
-    match (trigger, event) {
-        (Trigger::Key(trigger_code), Event::Keyboard(event)) => {
-            if &event.key() != trigger_code {
+/// Where input events are read from.
+enum InputSource {
+    /// Every device on a seat, found through udev.
+    Seat(String),
+    /// Only the given device files, opened without udev.
+    Paths(Vec<String>),
+}
+
+impl InputSource {
+    fn new(seat: Option<String>, paths: Vec<String>) -> Self {
+        if paths.is_empty() {
+            InputSource::Seat(seat.unwrap_or("seat0".to_string()))
+        } else {
+            InputSource::Paths(paths)
+        }
+    }
+
+    /// Opens the input and returns the devices that were found.
+    fn open(&self) -> (Libinput, Vec<Device>) {
+        let mut input = match self {
+            InputSource::Seat(seat) => {
+                let mut input = Libinput::new_with_udev(Interface);
+                input
+                    .udev_assign_seat(seat)
+                    .unwrap_or_else(|_| panic!("Could not connect to seat: {seat}"));
+                input
+            }
+            InputSource::Paths(paths) => {
+                let mut input = Libinput::new_from_path(Interface);
+                for path in paths {
+                    input
+                        .path_add_device(path)
+                        .unwrap_or_else(|| panic!("Could not open device: {path}"));
+                }
+                input
+            }
+        };
+        input.dispatch().expect("Could not read devices.");
+
+        let devices: Vec<Device> = (&mut input)
+            .filter_map(|event| match event {
+                Event::Device(DeviceEvent::Added(event)) => Some(event.device()),
+                _ => None,
+            })
+            .collect();
+
+        if let InputSource::Seat(seat) = self
+            && devices.is_empty()
+        {
+            panic!(
+                "No input devices found on seat: {seat}. Check the seat name and that you are in the input group."
+            );
+        }
+
+        (input, devices)
+    }
+}
+
+/// Matches `text` against a pattern where `*` matches any run of characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == text,
+        Some((prefix, rest)) => text.strip_prefix(prefix).is_some_and(|text| {
+            text.char_indices()
+                .map(|(index, _)| index)
+                .chain([text.len()])
+                .any(|index| glob_matches(rest, &text[index..]))
+        }),
+    }
+}
+
+fn device_matches(pattern: &str, device: &Device) -> bool {
+    identity_matches(
+        pattern,
+        device.name(),
+        device.sysname(),
+        device.id_vendor(),
+        device.id_product(),
+    )
+}
+
+/// Whether the pattern matches a device's name, sysname or `vendor:product`
+/// id.
+fn identity_matches(pattern: &str, name: &str, sysname: &str, vendor: u32, product: u32) -> bool {
+    let id = format!("{vendor:04x}:{product:04x}");
+
+    [name.to_lowercase(), sysname.to_lowercase(), id]
+        .iter()
+        .any(|field| glob_matches(pattern, field))
+}
+
+fn is_trigger(block: &Block, event: &Event, history: &KeyHistory) -> Option<bool> {
+    let is_pressed = match (&block.trigger, event) {
+        (Trigger::Key(trigger_key), Event::Keyboard(event)) => {
+            if !trigger_key.matches(event.key()) {
                 return None;
             }
 
-            Some(matches!(event.key_state(), KeyState::Pressed))
+            matches!(event.key_state(), KeyState::Pressed)
         }
         (Trigger::Mouse(trigger_code), Event::Pointer(PointerEvent::Button(button))) => {
-            if &(button.button() - 271) != trigger_code {
+            if &button.button() != trigger_code {
                 return None;
             }
 
-            Some(matches!(&button.button_state(), ButtonState::Pressed))
+            matches!(&button.button_state(), ButtonState::Pressed)
         }
-        _ => None,
+        (Trigger::DeviceAdded(pattern), Event::Device(DeviceEvent::Added(event))) => {
+            return device_matches(pattern, &event.device()).then_some(true);
+        }
+        (Trigger::Sequence { steps, timeout }, Event::Keyboard(event)) => {
+            if !matches!(event.key_state(), KeyState::Pressed) || !history.matches(steps, *timeout)
+            {
+                return None;
+            }
+
+            true
+        }
+        _ => return None,
+    };
+
+    if let Some(pattern) = &block.device
+        && !device_matches(pattern, &event.device())
+    {
+        return None;
+    }
+
+    Some(is_pressed)
+}
+
+/// The keys and buttons that are down and the lock keys that are on.
+#[derive(Clone)]
+struct InputState {
+    keys: HashSet<u32>,
+    buttons: HashSet<u32>,
+    locks: HashSet<u32>,
+}
+
+impl InputState {
+    /// Starts with the locks that are on according to the keyboard LEDs, as
+    /// they may have been turned on before the script started.
+    fn new() -> Self {
+        let locks = std::fs::read_dir("/sys/class/leds")
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                let code = match name.rsplit_once("::")?.1 {
+                    "capslock" => LOCK_KEYS[0],
+                    "numlock" => LOCK_KEYS[1],
+                    "scrolllock" => LOCK_KEYS[2],
+                    _ => return None,
+                };
+
+                let brightness = std::fs::read_to_string(entry.path().join("brightness")).ok()?;
+                (brightness.trim() != "0").then_some(code)
+            })
+            .collect();
+
+        Self {
+            keys: HashSet::new(),
+            buttons: HashSet::new(),
+            locks,
+        }
+    }
+
+    fn update(&mut self, event: &Event) {
+        match event {
+            Event::Keyboard(key) => {
+                let code = key.key();
+
+                if let KeyState::Pressed = key.key_state() {
+                    self.keys.insert(code);
+
+                    if LOCK_KEYS.contains(&code) && !self.locks.remove(&code) {
+                        self.locks.insert(code);
+                    }
+                } else {
+                    self.keys.remove(&code);
+                }
+            }
+            Event::Pointer(PointerEvent::Button(button)) => {
+                if let ButtonState::Pressed = button.button_state() {
+                    self.buttons.insert(button.button());
+                } else {
+                    self.buttons.remove(&button.button());
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks a condition for a block that is running. Events that haven't been
+/// handled yet are taken into account, and new ones are read into `pending`
+/// so none are lost.
+fn check_condition(
+    condition: &Condition,
+    block: &Block,
+    is_pressed: bool,
+    state: &InputState,
+    input: &mut Libinput,
+    pending: &mut VecDeque<Event>,
+    history: &KeyHistory,
+) -> bool {
+    if input.dispatch().is_ok() {
+        pending.extend(&mut *input);
+    }
+
+    // The state once the pending events are applied
+    let current = || {
+        let mut state = state.clone();
+        for event in pending.iter() {
+            state.update(event);
+        }
+        state
+    };
+
+    match condition {
+        Condition::TriggerHeld => {
+            is_pressed
+                && pending
+                    .iter()
+                    .rev()
+                    .find_map(|event| is_trigger(block, event, history))
+                    .unwrap_or(true)
+        }
+        Condition::KeyHeld(key) => current().keys.iter().any(|code| key.matches(*code)),
+        Condition::ButtonHeld(code) => current().buttons.contains(code),
+        Condition::Toggled(key) => current().locks.contains(&key.code),
+    }
+}
+
+/// A key press remembered for sequence triggers.
+struct KeyPress {
+    code: u32,
+    time: u64,
+    /// The other keys that were down at the time.
+    held: Vec<u32>,
+}
+
+/// A rolling buffer of recent key presses.
+struct KeyHistory {
+    presses: VecDeque<KeyPress>,
+}
+
+impl KeyHistory {
+    const CAPACITY: usize = 32;
+
+    fn new() -> Self {
+        Self {
+            presses: VecDeque::new(),
+        }
+    }
+
+    /// Remembers a key press, `held` is the keys that were already down.
+    fn update(&mut self, event: &KeyboardEvent, held: &HashSet<u32>) {
+        let code = event.key();
+
+        if matches!(event.key_state(), KeyState::Pressed) {
+            if self.presses.len() == Self::CAPACITY {
+                self.presses.pop_front();
+            }
+
+            self.presses.push_back(KeyPress {
+                code,
+                time: event.time_usec(),
+                held: held.iter().copied().collect(),
+            });
+        }
+    }
+
+    /// Whether the most recent key presses match the steps of a sequence.
+    fn matches(&self, steps: &[Vec<TriggerKey>], timeout: Duration) -> bool {
+        // Keys this sequence only holds for a chord don't count as steps
+        let chord_keys: HashSet<u32> = steps
+            .iter()
+            .flat_map(|step| &step[..step.len().saturating_sub(1)])
+            .flat_map(|key| [Some(key.code), key.right])
+            .flatten()
+            .filter(|code| {
+                !steps
+                    .iter()
+                    .any(|step| step.last().is_some_and(|key| key.matches(*code)))
+            })
+            .collect();
+        let presses: Vec<&KeyPress> = self
+            .presses
+            .iter()
+            .rev()
+            .filter(|press| !chord_keys.contains(&press.code))
+            .take(steps.len())
+            .collect();
+
+        if presses.len() < steps.len() {
+            return false;
+        }
+
+        let mut previous: Option<u64> = None;
+
+        for (press, step) in presses.into_iter().rev().zip(steps) {
+            let Some((key, chord)) = step.split_last() else {
+                return false;
+            };
+
+            let is_held = |key: &TriggerKey| press.held.iter().any(|code| key.matches(*code));
+            if !key.matches(press.code) || !chord.iter().all(is_held) {
+                return false;
+            }
+
+            // Presses from different devices can arrive slightly out of order
+            if previous.is_some_and(|previous| {
+                press.time.saturating_sub(previous) > timeout.as_micros() as u64
+            }) {
+                return false;
+            }
+            previous = Some(press.time);
+        }
+
+        true
+    }
+}
+
+/// Follows what is being typed so hotstrings can be expanded.
+struct TypedText {
+    state: xkb::State,
+    word: String,
+}
+
+impl TypedText {
+    /// Loads the keymap named by the XKB_DEFAULT_* variables, or xkbcommon's
+    /// default (`us`), the desktop's layout isn't read. Without xkeyboard-config
+    /// there is no keymap and hotstrings are turned off.
+    fn new() -> Option<Self> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let Some(keymap) = xkb::Keymap::new_from_names(
+            &context,
+            "",
+            "",
+            "",
+            "",
+            None,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        ) else {
+            log::error!(
+                "Could not load a keymap, hotstrings are turned off. Is xkeyboard-config installed?"
+            );
+            return None;
+        };
+
+        Some(Self {
+            state: xkb::State::new(&keymap),
+            word: String::new(),
+        })
+    }
+
+    /// Updates the word being typed, returns the word and the character that
+    /// ended it when one is finished.
+    fn update(&mut self, event: &KeyboardEvent) -> Option<(String, char)> {
+        // xkb uses X11 keycodes, which are offset from evdev codes by 8
+        let keycode = xkb::Keycode::new(event.key() + 8);
+
+        if let KeyState::Released = event.key_state() {
+            self.state.update_key(keycode, xkb::KeyDirection::Up);
+            return None;
+        }
+
+        let text = self.state.key_get_utf8(keycode);
+        let is_modifier = self.state.key_get_one_sym(keycode).is_modifier_key();
+        self.state.update_key(keycode, xkb::KeyDirection::Down);
+
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (None, _) if is_modifier => {}
+            (Some('\u{8}'), None) => {
+                self.word.pop();
+            }
+            (Some(c), None) if Hotstring::is_end_char(c) => {
+                return Some((std::mem::take(&mut self.word), c));
+            }
+            (Some(c), None) if !c.is_control() => self.word.push(c),
+            // Anything else, like arrows, enter or shortcuts, could have moved the cursor
+            _ => self.word.clear(),
+        }
+
+        None
+    }
+}
+
+/// Microseconds on the same clock libinput uses for event times.
+fn monotonic_usec() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+
+    now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000
+}
+
+fn event_time(event: &Event) -> u64 {
+    match event {
+        Event::Keyboard(event) => event.time_usec(),
+        Event::Pointer(PointerEvent::Button(button)) => button.time_usec(),
+        _ => monotonic_usec(),
+    }
+}
+
+/// Tracks a block's trigger between events to handle repeating and timing
+/// based triggers.
+#[derive(Default)]
+struct TriggerState {
+    /// The block is repeating until the trigger is released.
+    held: bool,
+    pressed_at: Option<u64>,
+    last_press: Option<u64>,
+    /// A hold trigger has fired since it was pressed, so releasing it runs
+    /// the release actions.
+    hold_fired: bool,
+    /// Where the precise sleeps of a repeating block got to, so each pass
+    /// carries on from the last instead of from now.
+    deadline: Option<u64>,
+}
+
+impl TriggerState {
+    /// Updates the state from a trigger event, returns the actions to run
+    /// now.
+    fn update<'a>(
+        &mut self,
+        block: &'a Block,
+        is_pressed: bool,
+        time: u64,
+    ) -> Option<&'a [Action]> {
+        if !is_pressed {
+            self.held = false;
+            self.deadline = None;
+        }
+
+        let release_actions =
+            (!block.release_actions.is_empty()).then_some(block.release_actions.as_slice());
+
+        let fired = match (block.timing, is_pressed) {
+            (None, true) => true,
+            (None, false) => return release_actions,
+            (Some(Timing::Hold(_) | Timing::Tap(_)), true) => {
+                self.pressed_at = Some(time);
+                self.hold_fired = false;
+                false
+            }
+            (Some(Timing::Double(duration)), true) => match self.last_press.replace(time) {
+                Some(last) if time.saturating_sub(last) <= duration.as_micros() as u64 => {
+                    self.last_press = None;
+                    true
+                }
+                _ => false,
+            },
+            (Some(Timing::Tap(duration)), false) => self
+                .pressed_at
+                .take()
+                .is_some_and(|pressed| time.saturating_sub(pressed) < duration.as_micros() as u64),
+            (Some(Timing::Hold(_)), false) => {
+                self.pressed_at = None;
+                let hold_fired = std::mem::take(&mut self.hold_fired);
+                return release_actions.filter(|_| hold_fired);
+            }
+            (Some(Timing::Double(_)), false) => false,
+        };
+
+        (fired && self.fire(block)).then_some(block.actions.as_slice())
+    }
+
+    /// Checks for hold triggers that have been held long enough.
+    fn poll(&mut self, block: &Block, now: u64) -> bool {
+        if let Some(Timing::Hold(duration)) = block.timing
+            && let Some(pressed) = self.pressed_at
+            && now.saturating_sub(pressed) >= duration.as_micros() as u64
+        {
+            self.pressed_at = None;
+            self.hold_fired = true;
+            return self.fire(block);
+        }
+
+        false
+    }
+
+    fn fire(&mut self, block: &Block) -> bool {
+        if block.is_repeating {
+            self.held = true;
+            return false;
+        }
+
+        true
+    }
+}
+
+fn log_device_change(event: &Event) {
+    match event {
+        Event::Device(DeviceEvent::Added(event)) => {
+            let device = event.device();
+            log::info!("Device added: {} ({})", device.name(), device.sysname());
+        }
+        Event::Device(DeviceEvent::Removed(event)) => {
+            let device = event.device();
+            log::info!("Device removed: {} ({})", device.name(), device.sysname());
+        }
+        _ => {}
     }
 }
 
@@ -70,13 +571,109 @@ fn button_from_u16(x: u16) -> Option<Button> {
     }
 }
 
-fn safe_sleep(duration: u64) {
-    let end = Instant::now() + Duration::from_millis(duration);
+/// Evaluates an expression while the script runs, anything that can't be
+/// evaluated is logged and counts as 0.
+fn evaluate(expr: &Expr, variables: &HashMap<String, i64>) -> i64 {
+    expr.eval(variables).unwrap_or_else(|| {
+        log::warn!("Could not evaluate {expr}, using 0");
+        0
+    })
+}
+
+/// Works out a duration, negative expressions count as 0.
+fn evaluate_duration(value: &DurationValue, variables: &HashMap<String, i64>) -> Duration {
+    match value {
+        DurationValue::Fixed(duration) => *duration,
+        DurationValue::Expr(expr) => {
+            Duration::from_millis(evaluate(expr, variables).max(0) as u64).min(MAX_DURATION)
+        }
+    }
+}
+
+/// Set by SIGINT and SIGTERM. Sleeps and loops end early and the script
+/// stops once the running actions are done, so held keys get released.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_signal: libc::c_int) {
+    STOPPING.store(true, Ordering::Relaxed);
+}
+
+fn is_stopping() -> bool {
+    STOPPING.load(Ordering::Relaxed)
+}
+
+/// Where actions send their events, Enigo when running and a recording in
+/// tests.
+trait Sender {
+    fn key(&mut self, key: Key, direction: Direction) -> enigo::InputResult<()>;
+    /// Takes an X11 keycode.
+    fn raw(&mut self, keycode: u16, direction: Direction) -> enigo::InputResult<()>;
+    fn button(&mut self, button: Button, direction: Direction) -> enigo::InputResult<()>;
+    fn text(&mut self, text: &str) -> enigo::InputResult<()>;
+}
+
+impl Sender for Enigo {
+    fn key(&mut self, key: Key, direction: Direction) -> enigo::InputResult<()> {
+        Keyboard::key(self, key, direction)
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> enigo::InputResult<()> {
+        Keyboard::raw(self, keycode, direction)
+    }
+
+    fn button(&mut self, button: Button, direction: Direction) -> enigo::InputResult<()> {
+        Mouse::button(self, button, direction)
+    }
+
+    fn text(&mut self, text: &str) -> enigo::InputResult<()> {
+        Keyboard::text(self, text)
+    }
+}
+
+/// Sends an event for a key or mouse button.
+fn send_key(
+    sender: &mut dyn Sender,
+    key: EventKey,
+    direction: Direction,
+) -> enigo::InputResult<()> {
+    match key {
+        EventKey::Key(key) => sender.key(key, direction),
+        // Enigo takes X11 keycodes, which are offset from evdev codes by 8
+        EventKey::Raw(code) => match code.checked_add(8) {
+            Some(keycode) => sender.raw(keycode, direction),
+            None => Err(enigo::InputError::InvalidInput("key code out of range")),
+        },
+    }
+}
+
+fn send_input(
+    sender: &mut dyn Sender,
+    input: &Input,
+    direction: Direction,
+) -> enigo::InputResult<()> {
+    match input {
+        Input::Key(key) => send_key(sender, key.key, direction),
+        Input::Mouse(code) => {
+            sender.button(button_from_u16(*code).unwrap_or(Button::Left), direction)
+        }
+    }
+}
+
+/// Logs events that couldn't be sent, the rest of the actions still run so
+/// that keys they release don't stay held.
+fn log_send_error(result: enigo::InputResult<()>) {
+    if let Err(error) = result {
+        log::error!("Could not send event: {error}");
+    }
+}
+
+fn safe_sleep(duration: Duration) {
+    let end = Instant::now() + duration;
 
     loop {
         let now = Instant::now();
 
-        if now >= end {
+        if now >= end || is_stopping() {
             break;
         }
 
@@ -85,13 +682,132 @@ fn safe_sleep(duration: u64) {
     }
 }
 
+/// Writes to a temporary file next to `path` and renames it over `path`, so
+/// the file is never left half written.
+fn replace_file(path: &str, contents: &str) {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| panic!("File not found: {path}"));
+    let mut temp = path.clone().into_os_string();
+    temp.push(".fmt-tmp");
+
+    std::fs::write(&temp, contents)
+        .and_then(|_| std::fs::set_permissions(&temp, std::fs::metadata(&path)?.permissions()))
+        .and_then(|_| std::fs::rename(&temp, &path))
+        .unwrap_or_else(|_| {
+            let _ = std::fs::remove_file(&temp);
+            panic!("Could not write file: {}", path.display())
+        });
+}
+
+/// How long before a precise deadline to stop sleeping and spin instead, in
+/// us. The kernel can wake a sleeping thread late by about this much.
+const SPIN_TIME: u64 = 500;
+
+/// Sleeps until `deadline`, in us on the monotonic clock, to within a few us
+/// by spinning for the last part.
+fn precise_sleep_until(deadline: u64) {
+    if let Some(wake) = deadline.checked_sub(SPIN_TIME) {
+        let wake = libc::timespec {
+            tv_sec: (wake / 1_000_000) as libc::time_t,
+            tv_nsec: (wake % 1_000_000 * 1_000) as libc::c_long,
+        };
+
+        // An absolute deadline stays the same when the sleep is interrupted
+        while unsafe {
+            libc::clock_nanosleep(
+                libc::CLOCK_MONOTONIC,
+                libc::TIMER_ABSTIME,
+                &wake,
+                std::ptr::null_mut(),
+            )
+        } == libc::EINTR
+            && !is_stopping()
+        {}
+    }
+
+    while monotonic_usec() < deadline && !is_stopping() {
+        std::hint::spin_loop();
+    }
+}
+
+/// Picks random sleep and click times, the same seed always gives the same
+/// times.
+struct Rng {
+    /// Printed when the runner starts, so a run can be repeated with `--seed`.
+    seed: u64,
+    /// Whether the seed came from the clock rather than `--seed`.
+    from_clock: bool,
+    state: Cell<u64>,
+}
+
+impl Rng {
+    /// Seeds from `--seed`, or the clock when there isn't one.
+    fn new(seed: Option<u64>) -> Self {
+        let from_clock = seed.is_none();
+        let seed = seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_nanos() as u64)
+        });
+
+        Self {
+            seed,
+            from_clock,
+            state: Cell::new(seed),
+        }
+    }
+
+    /// Splitmix64, any seed including 0 works.
+    fn next(&self) -> u64 {
+        self.state
+            .set(self.state.get().wrapping_add(0x9e3779b97f4a7c15));
+
+        let mut z = self.state.get();
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A duration from `min` to `max`, including both, in whole us.
+    fn between(&self, min: Duration, max: Duration) -> Duration {
+        let span = (max - min).as_micros() as u64;
+        let offset = match span.checked_add(1) {
+            Some(span) => self.next() % span,
+            None => self.next(),
+        };
+
+        min + Duration::from_micros(offset)
+    }
+
+    /// Moves a duration up to `jitter` either way, without going below 0.
+    fn jitter(&self, duration: Duration, jitter: Duration) -> Duration {
+        (duration + self.between(Duration::ZERO, 2 * jitter)).saturating_sub(jitter)
+    }
+}
+
+/// How deep macros can call each other before calls are skipped, macros can
+/// call themselves from inside an `if` or `while`.
+const MAX_CALL_DEPTH: usize = 64;
+
 pub struct Runner {
     script: Script,
+    source: InputSource,
+    call_depth: Cell<usize>,
+    rng: Rng,
+    /// Whether sleeps use `precise_sleep_until`.
+    precise: bool,
+    /// When the last sleep of the running actions ended, in precise mode
+    /// sleeps are timed from here so time spent sending events isn't added.
+    deadline: Cell<Option<u64>>,
 }
 
 impl Runner {
     pub fn new() -> Self {
         let mut script_path: Option<String> = None;
+        let mut seat: Option<String> = None;
+        let mut devices: Vec<String> = Vec::new();
+        let mut include_dirs: Vec<PathBuf> = Vec::new();
+        let mut seed: Option<u64> = None;
+        let mut precise = false;
         let mut args = std::env::args().skip(1);
 
         while let Some(arg) = args.next() {
@@ -100,6 +816,23 @@ impl Runner {
                 "-d" | "--debug" => unsafe {
                     std::env::set_var("RUST_LOG", "debug");
                 },
+                "-s" | "--seat" => seat = Some(args.next().expect("No seat passed.")),
+                "--device" => devices.push(args.next().expect("No device passed.")),
+                "--precise" => precise = true,
+                "--seed" => {
+                    seed = Some(
+                        args.next()
+                            .and_then(|seed| seed.parse().ok())
+                            .expect("No seed passed."),
+                    )
+                }
+                "--include-dir" => {
+                    include_dirs.push(args.next().expect("No include directory passed.").into())
+                }
+                "fmt" => Runner::format_scripts(args, &Runner::include_dirs(include_dirs)),
+                "list-keys" => Runner::list_keys(),
+                "list-devices" => Runner::list_devices(InputSource::new(seat, devices)),
+                "identify" => Runner::identify(InputSource::new(seat, devices)),
                 arg => {
                     if arg.starts_with('-') {
                         panic!("Unknown argument: {arg}");
@@ -111,80 +844,832 @@ impl Runner {
             }
         }
 
-        let script = Script::read(script_path.expect("No script path set."));
+        let script = Script::read(
+            script_path.expect("No script path set."),
+            &Runner::include_dirs(include_dirs),
+        )
+        .flatten();
+        if script.blocks.is_empty() && script.hotstrings.is_empty() {
+            panic!("No trigger set.");
+        }
+        let source = InputSource::new(seat.or(script.seat.clone()), devices);
 
-        Self { script }
+        Self {
+            script,
+            source,
+            call_depth: Cell::new(0),
+            rng: Rng::new(seed),
+            precise,
+            deadline: Cell::new(None),
+        }
     }
 
     fn display_help() {
         println!(
             r#"Usage: simple-hotkeys [OPTIONS]... FILE_PATH
+       simple-hotkeys fmt FILE_PATH...
+       simple-hotkeys list-keys | list-devices | identify
 Run a simple hotkey script
 
+Commands:
+    fmt                 Rewrite scripts in canonical form
+    list-keys           Display every key name and its code
+    list-devices        Display the input devices on the seat
+    identify            Display the trigger for each key or button pressed
+
 Options:
     -d, --debug         Turn on debug mode
+    -s, --seat NAME     Read input from NAME instead of seat0
+        --device PATH   Read input from PATH without udev, can be repeated
+        --include-dir DIR
+                        Look for included files in DIR, can be repeated
+        --seed NUMBER   Pick the same random sleep and click times every run
+        --precise       Time sleeps to within about a millisecond, uses more CPU
     -h, --help          Display this message"#
         );
 
         std::process::exit(0);
     }
 
-    fn execute_actions(&self, enigo: &mut Enigo) {
-        log::debug!("Trigger received!");
+    /// The directories passed with `--include-dir` followed by those in
+    /// `SHK_PATH`.
+    fn include_dirs(mut dirs: Vec<PathBuf>) -> Vec<PathBuf> {
+        if let Some(paths) = std::env::var_os("SHK_PATH") {
+            dirs.extend(std::env::split_paths(&paths));
+        }
+        dirs
+    }
+
+    /// Rewrites each script in the canonical layout.
+    fn format_scripts(paths: impl Iterator<Item = String>, include_dirs: &[PathBuf]) -> ! {
+        for path in paths {
+            let script = Script::read(path.clone(), include_dirs);
+            replace_file(&path, &script.to_string());
+        }
+
+        std::process::exit(0);
+    }
+
+    fn list_keys() -> ! {
+        println!("{:<6}{:<20}ALIASES", "CODE", "NAME");
+
+        for info in keys::KEYS {
+            let code = info.code.map_or("-".to_string(), |code| code.to_string());
+            let note = match (info.code, info.key) {
+                (None, _) => "(event only)",
+                (_, None) => "(sent as raw keycode)",
+                _ => "",
+            };
+            let aliases = info.names[1..].join(", ");
+
+            println!(
+                "{code:<6}{:<20}{}",
+                info.name(),
+                format!("{aliases} {note}").trim()
+            );
+        }
+
+        std::process::exit(0);
+    }
+
+    fn list_devices(source: InputSource) -> ! {
+        let capabilities = [
+            (DeviceCapability::Keyboard, "keyboard"),
+            (DeviceCapability::Pointer, "pointer"),
+            (DeviceCapability::Touch, "touch"),
+            (DeviceCapability::TabletTool, "tablet-tool"),
+            (DeviceCapability::TabletPad, "tablet-pad"),
+            (DeviceCapability::Gesture, "gesture"),
+            (DeviceCapability::Switch, "switch"),
+        ];
+
+        let (_input, devices) = source.open();
+
+        println!("{:<12}{:<11}{:<28}NAME", "SYSNAME", "ID", "CAPABILITIES");
+
+        for device in devices {
+            let id = format!("{:04x}:{:04x}", device.id_vendor(), device.id_product());
+            let capabilities = capabilities
+                .iter()
+                .filter(|(capability, _)| device.has_capability(*capability))
+                .map(|(_, name)| *name)
+                .collect::<Vec<_>>()
+                .join(",");
+
+            println!(
+                "{:<12}{id:<11}{capabilities:<28}{}",
+                device.sysname(),
+                device.name()
+            );
+        }
+
+        std::process::exit(0);
+    }
+
+    fn identify(source: InputSource) -> ! {
+        let (mut input, _) = source.open();
+
+        println!("Press a key or button to see its trigger, Ctrl+C to exit.");
+
+        loop {
+            if input.dispatch().is_err() {
+                continue;
+            }
+
+            for event in &mut input {
+                let line = match &event {
+                    Event::Keyboard(key) if matches!(key.key_state(), KeyState::Pressed) => {
+                        format!("on {}", Trigger::Key(TriggerKey::new(key.key())))
+                    }
+                    Event::Pointer(PointerEvent::Button(button))
+                        if matches!(button.button_state(), ButtonState::Pressed) =>
+                    {
+                        format!("on {}", Trigger::Mouse(button.button()))
+                    }
+                    Event::Pointer(PointerEvent::ScrollWheel(scroll)) => {
+                        let direction = if scroll.has_axis(Axis::Vertical) {
+                            let value = scroll.scroll_value(Axis::Vertical);
+                            if value > 0.0 { "down" } else { "up" }
+                        } else {
+                            let value = scroll.scroll_value(Axis::Horizontal);
+                            if value > 0.0 { "right" } else { "left" }
+                        };
+
+                        format!("# scroll {direction} can't be a trigger")
+                    }
+                    _ => continue,
+                };
+
+                let device = event.device();
+                println!("{line:<32}# {} ({})", device.name(), device.sysname());
+            }
+        }
+    }
+
+    fn sleep(&self, duration: Duration) {
+        if !self.precise {
+            safe_sleep(duration);
+            return;
+        }
+
+        // A schedule that has fallen behind by a whole sleep starts again from now
+        let now = monotonic_usec();
+        let deadline = (self.deadline.get().unwrap_or(now) + duration.as_micros() as u64).max(now);
+
+        precise_sleep_until(deadline);
+        self.deadline.set(Some(deadline));
+    }
+
+    /// Sends a key or button event, clicks are held for the block's
+    /// `click_time` if it has one.
+    fn send(
+        &self,
+        sender: &mut dyn Sender,
+        block: &Block,
+        direction: Direction,
+        send: impl Fn(&mut dyn Sender, Direction) -> enigo::InputResult<()>,
+    ) -> enigo::InputResult<()> {
+        match (direction, block.click_time) {
+            (Direction::Click, Some((min, max))) => {
+                send(sender, Direction::Press)?;
+                self.sleep(self.rng.between(min, max));
+                send(sender, Direction::Release)
+            }
+            _ => send(sender, direction),
+        }
+    }
+
+    /// Runs a list of actions from `block`, `check` answers the conditions
+    /// of `if` and `while`.
+    fn execute_actions(
+        &self,
+        actions: &[Action],
+        block: &Block,
+        sender: &mut dyn Sender,
+        variables: &mut HashMap<String, i64>,
+        check: &mut dyn FnMut(&Condition) -> bool,
+    ) {
+        for action in actions {
+            if is_stopping() {
+                return;
+            }
 
-        for action in &self.script.actions {
             log::debug!("Action: {:?}", action);
             match action {
-                Action::KeyEvent { key, direction } => enigo
-                    .key(*key, *direction)
-                    .expect("Could not send key event."),
-                Action::MouseEvent { code, direction } => enigo
-                    .button(button_from_u16(*code).unwrap_or(Button::Left), *direction)
-                    .expect("Could not send mouse event."),
-                Action::Sleep(duration) => {
-                    safe_sleep(*duration);
+                Action::KeyEvent { key, direction } => {
+                    log_send_error(self.send(sender, block, *direction, |sender, direction| {
+                        send_key(sender, key.key, direction)
+                    }))
+                }
+                Action::MouseEvent { code, direction } => {
+                    log_send_error(self.send(sender, block, *direction, |sender, direction| {
+                        send_input(sender, &Input::Mouse(*code), direction)
+                    }))
+                }
+                Action::Press { keys, delay } => {
+                    let (key, modifiers) = keys
+                        .split_last()
+                        .expect("Press actions have at least one key.");
+                    let delay = delay
+                        .as_ref()
+                        .map(|delay| evaluate_duration(delay, variables));
+                    let wait = || {
+                        if let Some(delay) = delay {
+                            self.sleep(delay);
+                        }
+                    };
+
+                    for modifier in modifiers {
+                        log_send_error(send_key(sender, modifier.key, Direction::Press));
+                        wait();
+                    }
+                    log_send_error(self.send(
+                        sender,
+                        block,
+                        Direction::Click,
+                        |sender, direction| send_key(sender, key.key, direction),
+                    ));
+                    for modifier in modifiers.iter().rev() {
+                        wait();
+                        log_send_error(send_key(sender, modifier.key, Direction::Release));
+                    }
+                }
+                Action::Hold { input, duration } => {
+                    log_send_error(send_input(sender, input, Direction::Press));
+                    // Stopping cuts the sleep short, so the release always follows
+                    self.sleep(evaluate_duration(duration, variables));
+                    log_send_error(send_input(sender, input, Direction::Release));
+                }
+                Action::Sleep { duration, max } => {
+                    let min = evaluate_duration(duration, variables);
+                    let max = max
+                        .as_ref()
+                        .map_or(min, |max| evaluate_duration(max, variables));
+                    let mut duration = self.rng.between(min.min(max), min.max(max));
+
+                    if let Some(jitter) = block.jitter {
+                        duration = self.rng.jitter(duration, jitter);
+                    }
+                    self.sleep(duration);
                 }
+                Action::Let { name, value } => {
+                    let value = evaluate(value, variables);
+                    variables.insert(name.clone(), value);
+                }
+                Action::Loop { kind, actions } => match kind {
+                    LoopKind::Count(count) => {
+                        for _ in (0..evaluate(count, variables)).take_while(|_| !is_stopping()) {
+                            self.execute_actions(actions, block, sender, variables, check);
+                        }
+                    }
+                    LoopKind::Forever => {
+                        while !is_stopping() {
+                            self.execute_actions(actions, block, sender, variables, check);
+                        }
+                    }
+                    LoopKind::While(condition) => {
+                        while !is_stopping() && check(condition) {
+                            self.execute_actions(actions, block, sender, variables, check);
+                        }
+                    }
+                },
+                Action::If {
+                    condition,
+                    actions,
+                    else_actions,
+                } => {
+                    let actions = if check(condition) {
+                        actions
+                    } else {
+                        else_actions
+                    };
+                    self.execute_actions(actions, block, sender, variables, check);
+                }
+                Action::Call { name, args } => {
+                    if self.call_depth.get() >= MAX_CALL_DEPTH {
+                        log::warn!("Calls are nested too deep, skipping call {name}");
+                        continue;
+                    }
+
+                    let script_macro = self
+                        .script
+                        .macros
+                        .iter()
+                        .find(|script_macro| &script_macro.name == name)
+                        .expect("Macros are checked when parsing.");
+
+                    // Parameters hide any variables with the same name until the call returns
+                    let values: Vec<i64> =
+                        args.iter().map(|arg| evaluate(arg, variables)).collect();
+                    let hidden: Vec<Option<i64>> = script_macro
+                        .params
+                        .iter()
+                        .zip(values)
+                        .map(|(param, value)| variables.insert(param.clone(), value))
+                        .collect();
+
+                    self.call_depth.set(self.call_depth.get() + 1);
+                    self.execute_actions(&script_macro.actions, block, sender, variables, check);
+                    self.call_depth.set(self.call_depth.get() - 1);
+
+                    for (param, value) in script_macro.params.iter().zip(hidden) {
+                        match value {
+                            Some(value) => variables.insert(param.clone(), value),
+                            None => variables.remove(param),
+                        };
+                    }
+                }
+                Action::Comment(_) => {}
             };
         }
     }
 
+    fn expand_hotstring(
+        &self,
+        word: &str,
+        end: char,
+        sender: &mut dyn Sender,
+        variables: &HashMap<String, i64>,
+    ) {
+        let word = word.to_lowercase();
+        let Some(hotstring) = self
+            .script
+            .hotstrings
+            .iter()
+            .find(|hotstring| hotstring.abbreviation == word)
+        else {
+            return;
+        };
+
+        log::debug!("Hotstring: {:?}", hotstring);
+
+        // The end character has already been typed, so it's removed and typed again
+        for _ in 0..hotstring.abbreviation.chars().count() + 1 {
+            log_send_error(sender.key(Key::Backspace, Direction::Click));
+        }
+        log_send_error(sender.text(&format!(
+            "{}{end}",
+            hotstring
+                .replacement
+                .render(|expr| evaluate(expr, variables))
+        )));
+    }
+
     pub fn run(&mut self) {
+        // The default log level only shows errors, so this is printed directly
+        if self.rng.from_clock {
+            eprintln!(
+                "Random seed: {0}, pass --seed {0} to repeat this run.",
+                self.rng.seed
+            );
+        }
         let mut enigo = Enigo::new(&Settings::default()).expect("Could not initialize enigo.");
-        let mut input = Libinput::new_with_udev(Interface);
-        input
-            .udev_assign_seat("seat0")
-            .expect("Could not connect to seat");
-
-        if self.script.is_repeating {
-            let mut trigger_down: bool = false;
-
-            loop {
-                if input.dispatch().is_ok() {
-                    for event in &mut input {
-                        if let Some(is_pressed) = is_trigger(&self.script.trigger, &event) {
-                            if trigger_down ^ is_pressed {
-                                trigger_down = is_pressed;
-                                break;
+        let (mut input, _) = self.source.open();
+        let mut states: Vec<TriggerState> = self
+            .script
+            .blocks
+            .iter()
+            .map(|_| TriggerState::default())
+            .collect();
+        let mut history = KeyHistory::new();
+        let mut input_state = InputState::new();
+        let mut typed = (!self.script.hotstrings.is_empty())
+            .then(TypedText::new)
+            .flatten();
+        let mut pending: VecDeque<Event> = VecDeque::new();
+        let mut variables: HashMap<String, i64> = HashMap::new();
+
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            unsafe { libc::signal(signal, stop as *const () as libc::sighandler_t) };
+        }
+
+        while !is_stopping() {
+            if input.dispatch().is_err() {
+                continue;
+            }
+            pending.extend(&mut input);
+
+            while let Some(event) = pending.pop_front() {
+                log_device_change(&event);
+
+                if let Event::Keyboard(key) = &event {
+                    history.update(key, &input_state.keys);
+                }
+                input_state.update(&event);
+
+                if let Some(typed) = &mut typed {
+                    match &event {
+                        Event::Keyboard(key) => {
+                            if let Some((word, end)) = typed.update(key) {
+                                self.expand_hotstring(&word, end, &mut enigo, &variables);
                             }
                         }
+                        // Clicking moves the cursor somewhere else
+                        Event::Pointer(PointerEvent::Button(_)) => typed.word.clear(),
+                        _ => {}
                     }
+                }
+
+                let mut sequence_fired = false;
+
+                for (block, state) in self.script.blocks.iter().zip(states.iter_mut()) {
+                    let Some(is_pressed) = is_trigger(block, &event, &history) else {
+                        continue;
+                    };
+
+                    if let Some(actions) = state.update(block, is_pressed, event_time(&event)) {
+                        log::debug!("Trigger received!");
+                        sequence_fired |= matches!(block.trigger, Trigger::Sequence { .. });
 
-                    if trigger_down {
-                        self.execute_actions(&mut enigo);
+                        self.deadline.set(state.deadline);
+                        self.execute_actions(
+                            actions,
+                            block,
+                            &mut enigo,
+                            &mut variables,
+                            &mut |condition| {
+                                check_condition(
+                                    condition,
+                                    block,
+                                    is_pressed,
+                                    &input_state,
+                                    &mut input,
+                                    &mut pending,
+                                    &history,
+                                )
+                            },
+                        );
+                        state.deadline = state.held.then(|| self.deadline.get()).flatten();
                     }
                 }
+
+                // Start over so the end of one sequence can't begin another
+                if sequence_fired {
+                    history.presses.clear();
+                }
             }
-        } else {
-            loop {
-                if input.dispatch().is_ok() {
-                    for event in &mut input {
-                        if is_trigger(&self.script.trigger, &event).unwrap_or(false) {
-                            self.execute_actions(&mut enigo);
-                            break;
-                        }
-                    }
+
+            let now = monotonic_usec();
+            for (block, state) in self.script.blocks.iter().zip(states.iter_mut()) {
+                if state.poll(block, now) || state.held {
+                    log::debug!("Trigger received!");
+                    self.deadline.set(state.deadline);
+                    self.execute_actions(
+                        &block.actions,
+                        block,
+                        &mut enigo,
+                        &mut variables,
+                        &mut |condition| {
+                            check_condition(
+                                condition,
+                                block,
+                                true,
+                                &input_state,
+                                &mut input,
+                                &mut pending,
+                                &history,
+                            )
+                        },
+                    );
+                    state.deadline = state.held.then(|| self.deadline.get()).flatten();
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs a trigger through `update`, returning whether the press actions,
+    /// the release actions or nothing would run. Times are in ms.
+    fn run(state: &mut TriggerState, block: &Block, is_pressed: bool, ms: u64) -> &'static str {
+        match state.update(block, is_pressed, ms * 1_000) {
+            Some(actions) if std::ptr::eq(actions, block.actions.as_slice()) => "press",
+            Some(_) => "release",
+            None => "",
+        }
+    }
+
+    /// Records events instead of sending them.
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl Sender for Recorder {
+        fn key(&mut self, key: Key, direction: Direction) -> enigo::InputResult<()> {
+            self.0.push(format!("{key:?} {direction:?}"));
+            Ok(())
+        }
+
+        fn raw(&mut self, keycode: u16, direction: Direction) -> enigo::InputResult<()> {
+            self.0.push(format!("raw {keycode} {direction:?}"));
+            Ok(())
+        }
+
+        fn button(&mut self, button: Button, direction: Direction) -> enigo::InputResult<()> {
+            self.0.push(format!("{button:?} {direction:?}"));
+            Ok(())
+        }
+
+        fn text(&mut self, text: &str) -> enigo::InputResult<()> {
+            self.0.push(format!("text {text}"));
+            Ok(())
+        }
+    }
+
+    /// Runs the actions of the first hotkey in `source` and returns the
+    /// events they send. Lock keys count as on, nothing else as held.
+    fn record(source: &str, variables: &mut HashMap<String, i64>) -> Vec<String> {
+        let runner = Runner {
+            script: Script::parse(source),
+            source: InputSource::Paths(Vec::new()),
+            call_depth: Cell::new(0),
+            rng: Rng::new(Some(0)),
+            precise: false,
+            deadline: Cell::new(None),
+        };
+        let block = &runner.script.blocks[0];
+        let mut recorder = Recorder::default();
+
+        runner.execute_actions(
+            &block.actions,
+            block,
+            &mut recorder,
+            variables,
+            &mut |condition| matches!(condition, Condition::Toggled(_)),
+        );
+        recorder.0
+    }
+
+    #[test]
+    fn press_order() {
+        assert_eq!(
+            record("on key:f1\npress ctrl+shift+t", &mut HashMap::new()),
+            [
+                "LControl Press",
+                "LShift Press",
+                "Unicode('t') Click",
+                "LShift Release",
+                "LControl Release"
+            ]
+        );
+        // Only the last key is held for the click time
+        assert_eq!(
+            record(
+                "on key:f1 click_time=1ms\npress alt+#183",
+                &mut HashMap::new()
+            ),
+            [
+                "Alt Press",
+                "raw 191 Press",
+                "raw 191 Release",
+                "Alt Release"
+            ]
+        );
+    }
+
+    #[test]
+    fn hold_releases() {
+        assert_eq!(
+            record(
+                "on key:f1\nhold key:w 1ms\nhold mouse:1 1ms",
+                &mut HashMap::new()
+            ),
+            [
+                "Unicode('w') Press",
+                "Unicode('w') Release",
+                "Left Press",
+                "Left Release"
+            ]
+        );
+    }
+
+    #[test]
+    fn loops_and_conditions() {
+        assert_eq!(
+            record(
+                "on key:f1\nrepeat 2 {\nevent key:a\n}\nif toggled capslock {\nevent key:b\n} else {\nevent key:c\n}\nif held key:shift {\nevent key:d\n} else {\nevent key:e\n}\nwhile held {\nevent key:f\n}",
+                &mut HashMap::new()
+            ),
+            [
+                "Unicode('a') Click",
+                "Unicode('a') Click",
+                "Unicode('b') Click",
+                "Unicode('e') Click"
+            ]
+        );
+    }
+
+    #[test]
+    fn macro_parameters() {
+        let mut variables = HashMap::new();
+        let events = record(
+            "macro keys count other {\nrepeat ${count} {\nevent key:a\n}\n}\non key:f1\nlet count = 3\ncall keys 1 2\nrepeat ${count} {\nevent key:b\n}",
+            &mut variables,
+        );
+
+        assert_eq!(
+            events.iter().filter(|event| event.contains("'a'")).count(),
+            1
+        );
+        assert_eq!(
+            events.iter().filter(|event| event.contains("'b'")).count(),
+            3
+        );
+        // Parameters give back the variables they hid and leave nothing behind
+        assert_eq!(variables, HashMap::from([("count".to_string(), 3)]));
+    }
+
+    #[test]
+    fn plain_triggers() {
+        let script = Script::parse("on key:a\nsleep 1\nrelease:\nsleep 2\non key:b r\nsleep 1");
+        let (plain, repeating) = (&script.blocks[0], &script.blocks[1]);
+
+        let mut state = TriggerState::default();
+        assert_eq!(run(&mut state, plain, true, 0), "press");
+        assert_eq!(run(&mut state, plain, false, 10), "release");
+
+        // Repeating blocks run from the main loop while `held` is set
+        let mut state = TriggerState::default();
+        assert_eq!(run(&mut state, repeating, true, 0), "");
+        assert!(state.held);
+        // Passes keep the precise sleep deadline until the trigger is released
+        state.deadline = Some(5_000);
+        assert_eq!(run(&mut state, repeating, false, 10), "");
+        assert!(!state.held);
+        assert_eq!(state.deadline, None);
+    }
+
+    #[test]
+    fn hold_triggers() {
+        let script = Script::parse("on key:a hold=200ms\nsleep 1\nrelease:\nsleep 2");
+        let block = &script.blocks[0];
+        let mut state = TriggerState::default();
+
+        assert_eq!(run(&mut state, block, true, 0), "");
+        assert!(!state.poll(block, 199_000));
+        assert!(state.poll(block, 200_000));
+        assert!(!state.poll(block, 300_000));
+        assert_eq!(run(&mut state, block, false, 400), "release");
+
+        // Let go too early, neither list runs
+        assert_eq!(run(&mut state, block, true, 1000), "");
+        assert_eq!(run(&mut state, block, false, 1100), "");
+        assert!(!state.poll(block, 2_000_000));
+    }
+
+    #[test]
+    fn double_triggers() {
+        let script = Script::parse("on key:a double=250ms\nsleep 1");
+        let block = &script.blocks[0];
+        let mut state = TriggerState::default();
+
+        assert_eq!(run(&mut state, block, true, 0), "");
+        assert_eq!(run(&mut state, block, false, 50), "");
+        assert_eq!(run(&mut state, block, true, 200), "press");
+        // A third press starts over
+        assert_eq!(run(&mut state, block, true, 300), "");
+        assert_eq!(run(&mut state, block, true, 600), "");
+        assert_eq!(run(&mut state, block, true, 700), "press");
+    }
+
+    #[test]
+    fn tap_triggers() {
+        let script = Script::parse("on key:a tap=200ms\nsleep 1");
+        let block = &script.blocks[0];
+        let mut state = TriggerState::default();
+
+        assert_eq!(run(&mut state, block, true, 0), "");
+        assert_eq!(run(&mut state, block, false, 150), "press");
+        assert_eq!(run(&mut state, block, true, 1000), "");
+        assert_eq!(run(&mut state, block, false, 1200), "");
+    }
+
+    fn history(presses: &[(u32, u64, &[u32])]) -> KeyHistory {
+        KeyHistory {
+            presses: presses
+                .iter()
+                .map(|(code, ms, held)| KeyPress {
+                    code: *code,
+                    time: ms * 1_000,
+                    held: held.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    fn steps(steps: &[&[u32]]) -> Vec<Vec<TriggerKey>> {
+        steps
+            .iter()
+            .map(|step| step.iter().map(|code| TriggerKey::new(*code)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn sequences() {
+        let (a, b, g, ctrl, space) = (30, 48, 34, 29, 57);
+        let second = Duration::from_secs(1);
+
+        assert!(history(&[(a, 0, &[]), (b, 500, &[])]).matches(&steps(&[&[a], &[b]]), second));
+        assert!(!history(&[(a, 0, &[]), (b, 1500, &[])]).matches(&steps(&[&[a], &[b]]), second));
+        assert!(!history(&[(b, 0, &[]), (a, 500, &[])]).matches(&steps(&[&[a], &[b]]), second));
+        assert!(!history(&[(b, 0, &[])]).matches(&steps(&[&[a], &[b]]), second));
+        // Out of order times don't underflow
+        assert!(history(&[(a, 500, &[]), (b, 0, &[])]).matches(&steps(&[&[a], &[b]]), second));
+
+        // The press of ctrl itself is skipped, but only for the sequence that
+        // uses it in a chord
+        let presses = history(&[(ctrl, 0, &[]), (space, 100, &[ctrl]), (g, 200, &[])]);
+        assert!(presses.matches(&steps(&[&[ctrl, space], &[g]]), second));
+        assert!(presses.matches(&steps(&[&[space], &[g]]), second));
+        assert!(!presses.matches(&steps(&[&[ctrl], &[g]]), second));
+        assert!(
+            history(&[(ctrl, 0, &[]), (ctrl, 100, &[])])
+                .matches(&steps(&[&[ctrl], &[ctrl]]), second)
+        );
+        assert!(
+            !history(&[(space, 0, &[]), (g, 100, &[])])
+                .matches(&steps(&[&[ctrl, space], &[g]]), second)
+        );
+
+        // Modifiers written without a side take either one
+        let script = Script::parse("on seq:ctrl+space,g");
+        let Trigger::Sequence { steps, .. } = &script.blocks[0].trigger else {
+            unreachable!()
+        };
+        let right_ctrl = 97;
+        let presses = history(&[
+            (right_ctrl, 0, &[]),
+            (space, 100, &[right_ctrl]),
+            (g, 200, &[]),
+        ]);
+        assert!(presses.matches(steps, second));
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob_matches("razer naga", "razer naga"));
+        assert!(!glob_matches("razer naga", "razer naga pro"));
+        assert!(glob_matches("razer*", "razer naga pro"));
+        assert!(glob_matches("*keyboard", "usb keyboard"));
+        assert!(!glob_matches("*keyboard", "usb keyboard mouse"));
+        assert!(glob_matches("razer*pro", "razer naga pro"));
+        assert!(glob_matches("*naga*", "razer naga pro"));
+        assert!(glob_matches("r*n*a", "razer naga"));
+        assert!(!glob_matches("r*x*a", "razer naga"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a**b", "ab"));
+    }
+
+    #[test]
+    fn device_identities() {
+        let matches = |pattern| identity_matches(pattern, "Razer Naga", "event5", 0x1532, 0x67);
+
+        assert!(matches("razer naga"));
+        assert!(matches("razer*"));
+        assert!(matches("event5"));
+        assert!(matches("event*"));
+        assert!(matches("1532:0067"));
+        assert!(matches("1532:*"));
+        assert!(!matches("1532:0068"));
+        assert!(!matches("046d:*"));
+    }
+
+    #[test]
+    fn seeded_randomness() {
+        let (first, second) = (Rng::new(Some(42)), Rng::new(Some(42)));
+        let numbers: Vec<u64> = (0..8).map(|_| first.next()).collect();
+        assert_eq!(numbers, (0..8).map(|_| second.next()).collect::<Vec<_>>());
+        let other = Rng::new(Some(43));
+        assert_ne!(numbers, (0..8).map(|_| other.next()).collect::<Vec<_>>());
+
+        let (min, max) = (Duration::from_millis(10), Duration::from_millis(12));
+        assert!((0..1000).all(|_| (min..=max).contains(&first.between(min, max))));
+        assert_eq!(first.between(min, min), min);
+    }
+
+    #[test]
+    fn long_expression_durations() {
+        let duration = DurationValue::Expr(Expr::Number(i64::MAX));
+        assert_eq!(evaluate_duration(&duration, &HashMap::new()), MAX_DURATION);
+    }
+
+    #[test]
+    fn jitter() {
+        let rng = Rng::new(Some(7));
+        let (duration, jitter) = (Duration::from_millis(100), Duration::from_millis(20));
+        let jittered: Vec<Duration> = (0..1000).map(|_| rng.jitter(duration, jitter)).collect();
+
+        assert!(
+            jittered
+                .iter()
+                .all(|d| (duration - jitter..=duration + jitter).contains(d))
+        );
+        assert!(jittered.iter().any(|d| *d < duration));
+        assert!(jittered.iter().any(|d| *d > duration));
+        // Short durations stop at 0 rather than wrapping
+        let short = Duration::from_millis(5);
+        assert!((0..1000).any(|_| rng.jitter(short, jitter).is_zero()));
+    }
+}